@@ -0,0 +1,48 @@
+//! Unpacking of password stores which are shipped as `.tar.gz` archives
+use anyhow::Context;
+use flate2::read::GzDecoder;
+use std::path::Path;
+use std::{fs, process};
+
+/// Unpack the gzip compressed tar archive *content* into the directory *path*
+///
+/// The archive is unpacked into a temporary directory which is only moved to *path* once it is complete so that an
+/// interrupted unpacking never leaves an incomplete store at *path*.
+/// Entries which would be placed outside of *path* are skipped.
+pub(crate) fn unpack_tar_gz(content: &[u8], path: &Path) -> anyhow::Result<()> {
+    log::debug!("Unpacking archive to {}", path.display());
+
+    let file_name = path
+        .file_name()
+        .context("Unpack destination does not have a file name")?
+        .to_string_lossy();
+    let tmp_path = path.with_file_name(format!("{}.tmp-{}", file_name, process::id()));
+    if tmp_path.exists() {
+        fs::remove_dir_all(&tmp_path)?;
+    }
+
+    let mut archive = tar::Archive::new(GzDecoder::new(content));
+    archive.set_preserve_permissions(false);
+    if let Err(e) = archive.unpack(&tmp_path) {
+        if let Err(remove_error) = fs::remove_dir_all(&tmp_path) {
+            log::warn!(
+                "Could not remove incompletely unpacked archive at {}: {}",
+                tmp_path.display(),
+                remove_error
+            );
+        }
+        return Err(e).context("Could not unpack the archive");
+    }
+
+    if path.exists() {
+        fs::remove_dir_all(path).context(format!(
+            "Could not remove previously unpacked archive at {}",
+            path.display()
+        ))?;
+    }
+    fs::rename(&tmp_path, path).context(format!(
+        "Could not move unpacked archive from {} to {}",
+        tmp_path.display(),
+        path.display()
+    ))
+}
@@ -0,0 +1,243 @@
+//! Management of the local cache in which git, bundle and archive sources are stored
+use crate::cache_metadata::{cache_dir, CacheMetadata};
+use crate::clone_lock;
+use anyhow::Context;
+use git2::Repository;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Marker in the file name of temporary directories in which clones and archives are prepared
+const TMP_MARKER: &str = ".tmp-";
+
+/// Extensions of the lock and metadata files which are stored next to each cache entry
+const ENTRY_FILE_EXTENSIONS: [&str; 2] = [".lock", ".yml"];
+
+/// Whether *name* has the form `<kind>-<hex digest>` of the names of cache entries
+///
+/// This distinguishes the files of cache entries from other files which may share the directory (e.g. the url
+/// overrides on macOS, where configuration and data are stored in the same directory).
+fn is_entry_name(name: &str) -> bool {
+    name.rsplit_once('-').is_some_and(|(_, digest)| {
+        !digest.is_empty() && digest.chars().all(|c| c.is_ascii_hexdigit())
+    })
+}
+
+/// Paths of all entries of the cache
+///
+/// Temporary directories of incomplete clones are attributed to the entry they belong to.
+/// Lock and metadata files whose clone does not exist (anymore) are listed as entries too so that they can be
+/// removed.
+fn cache_entries() -> anyhow::Result<Vec<PathBuf>> {
+    let cache_dir = cache_dir()?;
+    if !cache_dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut entries = BTreeSet::new();
+    for dir_entry in fs::read_dir(&cache_dir).context(format!(
+        "Could not read cache directory {}",
+        cache_dir.display()
+    ))? {
+        let dir_entry = dir_entry?;
+        let file_name = dir_entry.file_name().to_string_lossy().into_owned();
+        let name = if dir_entry.file_type()?.is_dir() {
+            match file_name.split_once(TMP_MARKER) {
+                Some((name, _)) => name.to_string(),
+                None => file_name,
+            }
+        } else {
+            match ENTRY_FILE_EXTENSIONS
+                .iter()
+                .find_map(|extension| file_name.strip_suffix(extension))
+                .filter(|name| is_entry_name(name))
+            {
+                Some(name) => name.to_string(),
+                None => continue,
+            }
+        };
+        entries.insert(cache_dir.join(name));
+    }
+    Ok(entries.into_iter().collect())
+}
+
+/// Temporary directories which belong to the cache entry at *path*
+fn temporary_dirs(path: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let (parent, name) = match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => (parent, name.to_string_lossy() + TMP_MARKER),
+        _ => return Ok(Vec::new()),
+    };
+    Ok(fs::read_dir(parent)?
+        .filter_map(Result::ok)
+        .filter(|dir_entry| dir_entry.file_name().to_string_lossy().starts_with(&*name))
+        .map(|dir_entry| dir_entry.path())
+        .collect())
+}
+
+/// Total size of all files below *path* in bytes
+fn dir_size(path: &Path) -> u64 {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => fs::read_dir(path)
+            .map(|dir_entries| {
+                dir_entries
+                    .filter_map(Result::ok)
+                    .map(|dir_entry| dir_size(&dir_entry.path()))
+                    .sum()
+            })
+            .unwrap_or(0),
+        Ok(metadata) => metadata.len(),
+        Err(_) => 0,
+    }
+}
+
+/// Format *size* in bytes in a human readable way
+pub(crate) fn format_size(size: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    if size < 1024 {
+        return format!("{size} B");
+    }
+    let mut value = size as f64 / 1024.0;
+    let mut unit = UNITS[0];
+    for next_unit in &UNITS[1..] {
+        if value < 1024.0 {
+            break;
+        }
+        value /= 1024.0;
+        unit = next_unit;
+    }
+    format!("{value:.1} {unit}")
+}
+
+/// Format a time given in seconds since the unix epoch in a human readable way
+fn format_time(time: Option<u64>) -> String {
+    match time {
+        None => "never".to_string(),
+        Some(time) => {
+            humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(time)).to_string()
+        }
+    }
+}
+
+/// The time at which the cache entry at *path* was last used
+///
+/// Entries which were created before usage times were recorded fall back to their last fetch or modification time.
+fn last_used(path: &Path, metadata: &CacheMetadata) -> Option<SystemTime> {
+    metadata
+        .last_used
+        .or(metadata.last_fetch)
+        .map(|time| UNIX_EPOCH + Duration::from_secs(time))
+        .or_else(|| {
+            fs::metadata(path)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+        })
+}
+
+/// Remove the cache entry at *path* together with its metadata, lock file and temporary directories
+///
+/// The entry is locked first so that it is not removed while another process uses it.
+/// Processes which are already waiting for the lock notice that its file was removed and lock the entry again.
+fn remove_entry(path: &Path) -> anyhow::Result<()> {
+    clone_lock::lock_clone(path)?;
+
+    for dir in temporary_dirs(path)?
+        .into_iter()
+        .chain(Some(path.to_owned()).filter(|path| path.exists()))
+    {
+        log::debug!("Removing {}", dir.display());
+        fs::remove_dir_all(&dir).context(format!("Could not remove {}", dir.display()))?;
+    }
+    for file in [
+        CacheMetadata::file_path(path),
+        clone_lock::lock_file_path(path),
+    ] {
+        if file.exists() {
+            log::debug!("Removing {}", file.display());
+            fs::remove_file(&file).context(format!("Could not remove {}", file.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Print all entries of the cache together with their url, last fetch, checked out commit and size
+pub(crate) fn list_cache() -> anyhow::Result<()> {
+    let entries = cache_entries()?;
+    if entries.is_empty() {
+        println!("The cache at {} is empty", cache_dir()?.display());
+    }
+
+    for path in entries {
+        let metadata = CacheMetadata::load(&path);
+        let commit = Repository::open(&path)
+            .ok()
+            .and_then(|repo| repo.head().ok()?.target())
+            .map(|commit| commit.to_string());
+
+        println!("{}", path.display());
+        println!(
+            "  url:        {}",
+            metadata.url.as_deref().unwrap_or("unknown")
+        );
+        println!("  last fetch: {}", format_time(metadata.last_fetch));
+        println!("  last used:  {}", format_time(metadata.last_used));
+        println!("  commit:     {}", commit.as_deref().unwrap_or("none"));
+        println!("  size:       {}", format_size(dir_size(&path)));
+        if !path.is_dir() {
+            println!("  clone:      missing, only lock or metadata files are left");
+        }
+    }
+    Ok(())
+}
+
+/// Remove all entries of the cache which have not been used during the last *days* days
+pub(crate) fn prune_cache(days: u64) -> anyhow::Result<()> {
+    let threshold = SystemTime::now()
+        .checked_sub(Duration::from_secs(days * 24 * 60 * 60))
+        .unwrap_or(UNIX_EPOCH);
+
+    let mut removed = 0;
+    for path in cache_entries()? {
+        let metadata = CacheMetadata::load(&path);
+        let is_unused = match last_used(&path, &metadata) {
+            None => true,
+            Some(last_used) => last_used < threshold,
+        };
+        if is_unused {
+            remove_entry(&path)?;
+            println!(
+                "Removed {} ({})",
+                path.display(),
+                metadata.url.as_deref().unwrap_or("unknown url")
+            );
+            removed += 1;
+        }
+    }
+    println!("Removed {removed} cache entries which were not used during the last {days} days");
+    Ok(())
+}
+
+/// Remove all entries of the cache
+pub(crate) fn clear_cache() -> anyhow::Result<()> {
+    let entries = cache_entries()?;
+    for path in &entries {
+        remove_entry(path)?;
+    }
+    println!("Removed {} cache entries", entries.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_names_with_digest_are_entries() {
+        assert!(is_entry_name("password-store-ddc7afdb24c1c1dd152bfca5"));
+        assert!(is_entry_name("bundle-0a1b"));
+        assert!(!is_entry_name("url-overrides"));
+        assert!(!is_entry_name("password-store-"));
+        assert!(!is_entry_name("ddc7afdb24c1c1dd152bfca5"));
+    }
+}
@@ -0,0 +1,112 @@
+//! Metadata about cached clones of git password stores
+use anyhow::Context;
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The directory in which sources are cached locally
+pub(crate) fn cache_dir() -> anyhow::Result<PathBuf> {
+    Ok(ProjectDirs::from("de", "ftsell", "kustomize-pass")
+        .context("Could not retrieve project directories")?
+        .data_local_dir()
+        .to_path_buf())
+}
+
+/// The current time in seconds since the unix epoch
+fn now() -> Option<u64> {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|duration| duration.as_secs())
+}
+
+/// Metadata which is stored in a file next to each cached clone
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct CacheMetadata {
+    /// Url of the git source or path of the bundle or archive from which the cache entry was created
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) url: Option<String>,
+
+    /// Time at which the cache entry was last used in seconds since the unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) last_used: Option<u64>,
+
+    /// Time of the last successful fetch in seconds since the unix epoch
+    pub(crate) last_fetch: Option<u64>,
+
+    /// Url of the remote or mirror from which the clone was last fetched
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) fetched_from: Option<String>,
+
+    /// Hash of the last commit whose signature was successfully verified
+    pub(crate) last_verified_commit: Option<String>,
+
+    /// Paths relative to the repository root which were written to the working directory by the last checkout
+    ///
+    /// Absent if the whole repository was checked out.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) checked_out_paths: Option<Vec<String>>,
+}
+
+impl CacheMetadata {
+    /// Path of the metadata file belonging to the clone at *clone_path*
+    pub(crate) fn file_path(clone_path: &Path) -> PathBuf {
+        clone_path.with_extension("yml")
+    }
+
+    /// Load the metadata of the clone at *clone_path*
+    ///
+    /// Missing or unreadable metadata is treated as empty because it can always be reconstructed.
+    pub(crate) fn load(clone_path: &Path) -> Self {
+        let file_path = Self::file_path(clone_path);
+        fs::read_to_string(&file_path)
+            .ok()
+            .and_then(|content| match serde_yaml::from_str(&content) {
+                Ok(metadata) => Some(metadata),
+                Err(e) => {
+                    log::warn!(
+                        "Ignoring invalid cache metadata at {}: {}",
+                        file_path.display(),
+                        e
+                    );
+                    None
+                }
+            })
+            .unwrap_or_default()
+    }
+
+    /// Store the metadata of the clone at *clone_path*
+    pub(crate) fn store(&self, clone_path: &Path) -> anyhow::Result<()> {
+        let file_path = Self::file_path(clone_path);
+        fs::write(&file_path, serde_yaml::to_string(self)?).context(format!(
+            "Could not write cache metadata to {}",
+            file_path.display()
+        ))
+    }
+
+    /// Record that the clone has just been fetched successfully from *url*
+    pub(crate) fn record_fetch(&mut self, url: &str) {
+        self.fetched_from = Some(url.to_string());
+        self.last_fetch = now();
+    }
+
+    /// Record that the cache entry created from *url* is being used right now
+    pub(crate) fn record_use(&mut self, url: &str) {
+        self.url = Some(url.to_string());
+        self.last_used = now();
+    }
+
+    /// Whether the last fetch happened less than *ttl* ago
+    pub(crate) fn is_fresh(&self, ttl: Duration) -> bool {
+        match self.last_fetch {
+            None => false,
+            Some(last_fetch) => SystemTime::now()
+                .duration_since(UNIX_EPOCH + Duration::from_secs(last_fetch))
+                .map(|age| age < ttl)
+                .unwrap_or(false),
+        }
+    }
+}
@@ -1,8 +1,9 @@
 //! Types and implementations for clap
 use clap::{Parser, Subcommand, ValueEnum};
+use std::path::PathBuf;
 
 /// Command-Line arguments that are passed to the program
-#[derive(Parser, Debug, Copy, Clone)]
+#[derive(Parser, Debug, Clone)]
 #[clap(
     version,
     about = "A kustomize plugin that is able to generate secrets by extracting them from or replace placeholders in other manifests from pass",
@@ -15,7 +16,7 @@ pub struct CliArgs {
 }
 
 /// Possible modes of operations
-#[derive(Subcommand, Debug, Copy, Clone)]
+#[derive(Subcommand, Debug, Clone)]
 pub enum CliCommand {
     /// Print the JSON schema for configuration types of this krm functions
     PrintSchema {
@@ -25,6 +26,58 @@ pub enum CliCommand {
     },
     /// Execute the main kustomize plugin functionality (default)
     ExecPlugin,
+    /// Record the commits that all git sources currently resolve to in kustomize-pass.lock
+    ///
+    /// Must be run in the directory of the kustomization.
+    /// Subsequent renders check out exactly the recorded commits.
+    UpdateLock {
+        /// Files containing the PassSecrets and PassTransformers whose sources should be locked
+        ///
+        /// Defaults to all generators and transformers of the kustomization in the current directory.
+        /// If files are given, existing entries of other sources are kept in the lockfile.
+        files: Vec<PathBuf>,
+    },
+    /// Commit the changed entries of git sources and push them to the remote branch of each source
+    ///
+    /// Entries are changed in the cached clones which are listed by `cache list`, e.g. by running pass with
+    /// PASSWORD_STORE_DIR pointing to one of them.
+    /// Nothing is pushed if the remote branch contains commits which are not part of the cached clone.
+    Push {
+        /// Files containing the PassSecrets and PassTransformers whose sources should be pushed
+        ///
+        /// Defaults to all generators and transformers of the kustomization in the current directory.
+        files: Vec<PathBuf>,
+        /// Subject of the commit message which is used instead of a generated one
+        #[clap(short = 'm', long = "message")]
+        message: Option<String>,
+        /// Sign the commits with gpg even if commit.gpgSign is not configured
+        #[clap(long = "sign")]
+        sign: bool,
+        /// Key with which the commits are signed instead of user.signingKey (implies --sign)
+        #[clap(long = "signing-key")]
+        signing_key: Option<String>,
+    },
+    /// Inspect or clean up the local cache of git, bundle and archive sources
+    Cache {
+        /// The operation which should be performed on the cache
+        #[clap(subcommand)]
+        command: CacheCommand,
+    },
+}
+
+/// Operations on the local cache of sources
+#[derive(Subcommand, Debug, Copy, Clone)]
+pub enum CacheCommand {
+    /// List all cached sources with their url, path, last fetch, checked out commit and size
+    List,
+    /// Remove cached sources which have not been used for some time
+    Prune {
+        /// Number of days after which an unused source is removed
+        #[clap(long = "days", default_value = "30")]
+        days: u64,
+    },
+    /// Remove all cached sources
+    Clear,
 }
 
 /// Possible formats in which schemas can be printed
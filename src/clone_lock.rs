@@ -0,0 +1,137 @@
+//! Advisory locking of cached clones so that multiple processes can use the same cache concurrently
+//!
+//! kustomize (and tools which embed it) run many functions in parallel which all operate on the same cached
+//! clones.
+//! Each process therefore locks a clone before modifying or reading it and keeps the lock until it exits.
+use crate::env_config;
+use anyhow::{bail, Context};
+use fs2::FileExt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Locks which are held by this process together with the clone they belong to
+static HELD_LOCKS: Mutex<Vec<(PathBuf, File)>> = Mutex::new(Vec::new());
+
+/// Interval in which an already held lock is tried again
+const RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Duration after which waiting for a lock is reported to the user
+const REPORT_AFTER: Duration = Duration::from_secs(2);
+
+/// Path of the lock file belonging to the clone at *clone_path*
+pub(crate) fn lock_file_path(clone_path: &Path) -> PathBuf {
+    clone_path.with_extension("lock")
+}
+
+/// Read the id of the process which holds the lock, if it recorded one
+fn lock_holder(lock_file: &mut File) -> Option<String> {
+    let mut content = String::new();
+    lock_file.seek(SeekFrom::Start(0)).ok()?;
+    lock_file.read_to_string(&mut content).ok()?;
+    Some(content.trim().to_string()).filter(|content| !content.is_empty())
+}
+
+/// Whether *lock_file* is still the file at *path*
+///
+/// This is not the case if the file was removed (e.g. by `cache clear`) while this process waited for the lock.
+fn is_current(lock_file: &File, path: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        match (lock_file.metadata(), fs::metadata(path)) {
+            (Ok(locked), Ok(current)) => {
+                locked.dev() == current.dev() && locked.ino() == current.ino()
+            }
+            _ => false,
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = lock_file;
+        path.exists()
+    }
+}
+
+/// Lock the clone at *clone_path* for the remaining lifetime of this process
+///
+/// If another process holds the lock, this waits for at most the configured lock timeout.
+/// Locking a clone which is already locked by this process does nothing.
+pub(crate) fn lock_clone(clone_path: &Path) -> anyhow::Result<()> {
+    let mut held_locks = HELD_LOCKS.lock().unwrap_or_else(|e| e.into_inner());
+    if held_locks.iter().any(|(path, _)| path == clone_path) {
+        return Ok(());
+    }
+
+    let lock_file_path = lock_file_path(clone_path);
+    if let Some(parent) = lock_file_path.parent() {
+        fs::create_dir_all(parent).context(format!(
+            "Could not create cache directory {}",
+            parent.display()
+        ))?;
+    }
+    log::debug!("Locking cached clone at {}", clone_path.display());
+    let timeout = env_config::lock_timeout()?;
+    let start = Instant::now();
+    let mut reported = false;
+    let mut lock_file = loop {
+        let mut lock_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&lock_file_path)
+            .context(format!(
+                "Could not open lock file {}",
+                lock_file_path.display()
+            ))?;
+
+        while let Err(e) = lock_file.try_lock_exclusive() {
+            if e.kind() != fs2::lock_contended_error().kind() {
+                return Err(e).context(format!("Could not lock {}", lock_file_path.display()));
+            }
+
+            let waited = start.elapsed();
+            if waited >= timeout {
+                bail!(
+                    "Timed out after {} waiting for the cached clone at {} which is locked by {}. \
+                    If that process is expected to take this long, increase the timeout with {}",
+                    humantime::format_duration(timeout),
+                    clone_path.display(),
+                    lock_holder(&mut lock_file)
+                        .map(|pid| format!("process {pid}"))
+                        .unwrap_or_else(|| "another process".to_string()),
+                    env_config::LOCK_TIMEOUT_ENV
+                );
+            }
+            if !reported && waited >= REPORT_AFTER {
+                log::info!(
+                    "Waiting for another process to release the cached clone at {}",
+                    clone_path.display()
+                );
+                reported = true;
+            }
+            thread::sleep(RETRY_INTERVAL);
+        }
+
+        if is_current(&lock_file, &lock_file_path) {
+            break lock_file;
+        }
+        log::debug!(
+            "Lock file {} was removed while waiting for it, locking again",
+            lock_file_path.display()
+        );
+    };
+
+    // record which process holds the lock so that it can be named to waiting processes
+    lock_file.set_len(0)?;
+    lock_file.seek(SeekFrom::Start(0))?;
+    write!(lock_file, "{}", process::id())?;
+
+    held_locks.push((clone_path.to_owned(), lock_file));
+    Ok(())
+}
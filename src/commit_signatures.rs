@@ -0,0 +1,178 @@
+//! Creation and verification of OpenPGP signatures on git commits
+//!
+//! Signatures are created and verified by the `gpg` program using the keys in the keyring of the current user but are only
+//! accepted if they were made by one of an explicitly trusted set of keys.
+use anyhow::{bail, Context};
+use git2::{Oid, Repository, Sort};
+use std::io::Write;
+use subprocess::{Exec, Redirection};
+
+/// Prefix of the machine readable status lines which gpg writes to its status file descriptor
+const GPG_STATUS_PREFIX: &str = "[GNUPG:] ";
+
+/// Normalize a key fingerprint so that differently formatted fingerprints can be compared
+pub(crate) fn normalize_fingerprint(fingerprint: &str) -> String {
+    fingerprint
+        .trim_start_matches("0x")
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_uppercase()
+}
+
+/// Create an armored detached signature over *data* with gpg
+///
+/// The default key of gpg is used unless *signing_key* is given.
+pub(crate) fn sign(data: &[u8], signing_key: Option<&str>) -> anyhow::Result<String> {
+    let mut cmd = Exec::cmd("gpg").args(&["--status-fd", "2", "--detach-sign", "--armor"]);
+    if let Some(signing_key) = signing_key {
+        cmd = cmd.arg("--local-user").arg(signing_key);
+    }
+    let output = cmd
+        .stdin(data.to_vec())
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Pipe)
+        .capture()
+        .context("Could not execute gpg to sign the commit")?;
+    log::trace!("gpg output: {}", output.stderr_str());
+
+    let stderr = output.stderr_str();
+    if !output.success()
+        || !stderr
+            .lines()
+            .any(|line| line.starts_with(&format!("{GPG_STATUS_PREFIX}SIG_CREATED ")))
+    {
+        let messages = stderr
+            .lines()
+            .filter(|line| !line.starts_with(GPG_STATUS_PREFIX))
+            .collect::<Vec<_>>();
+        bail!("gpg could not sign the commit: {}", messages.join(" "));
+    }
+    Ok(output.stdout_str())
+}
+
+/// Verify *signature* over *data* with gpg and return the fingerprints of the key that made it
+///
+/// The returned fingerprints are those of the signing (sub)key and of its primary key.
+fn gpg_verify(signature: &[u8], data: &[u8]) -> anyhow::Result<Vec<String>> {
+    let mut signature_file =
+        tempfile::NamedTempFile::new().context("Could not create temporary signature file")?;
+    signature_file
+        .write_all(signature)
+        .context("Could not write temporary signature file")?;
+
+    let output = Exec::cmd("gpg")
+        .args(&["--batch", "--no-tty", "--status-fd", "1", "--verify"])
+        .arg(signature_file.path())
+        .arg("-")
+        .stdin(data.to_vec())
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Pipe)
+        .capture()
+        .context("Could not execute gpg to verify the signature")?;
+    log::trace!("gpg output: {}", output.stderr_str());
+
+    let status = output.stdout_str();
+    let status_lines = status
+        .lines()
+        .filter_map(|line| line.strip_prefix(GPG_STATUS_PREFIX))
+        .collect::<Vec<_>>();
+    if !output.success() || !status_lines.iter().any(|line| line.starts_with("GOODSIG ")) {
+        let reason = status_lines
+            .iter()
+            .find_map(|line| match line.split_whitespace().next() {
+                Some("NO_PUBKEY") => Some("The signing key is not in the gpg keyring"),
+                Some("EXPKEYSIG") => Some("The signing key is expired"),
+                Some("REVKEYSIG") => Some("The signing key is revoked"),
+                Some("BADSIG") => Some("The signature is invalid"),
+                _ => None,
+            })
+            .unwrap_or("gpg could not verify the signature");
+        bail!("{reason}");
+    }
+
+    // VALIDSIG <fingerprint> <date> ... <primary key fingerprint>
+    let fingerprints = status_lines
+        .iter()
+        .filter_map(|line| line.strip_prefix("VALIDSIG "))
+        .flat_map(|line| {
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            [fields.first().copied(), fields.get(9).copied()]
+        })
+        .flatten()
+        .map(normalize_fingerprint)
+        .collect::<Vec<_>>();
+    Ok(fingerprints)
+}
+
+/// Verify that *commit* carries a valid signature of one of the *trusted_fingerprints*
+fn verify_commit(
+    repo: &Repository,
+    commit: Oid,
+    trusted_fingerprints: &[String],
+) -> anyhow::Result<()> {
+    log::debug!("Verifying signature of commit {}", commit);
+
+    let (signature, data) = match repo.extract_signature(&commit, None) {
+        Ok(signature) => signature,
+        Err(e) if e.code() == git2::ErrorCode::NotFound => bail!("Commit {commit} is not signed"),
+        Err(e) => {
+            return Err(e).context(format!(
+                "Could not extract the signature of commit {commit}"
+            ))
+        }
+    };
+    let fingerprints = gpg_verify(&signature, &data)
+        .context(format!("Could not verify the signature of commit {commit}"))?;
+
+    match fingerprints
+        .iter()
+        .find(|fingerprint| trusted_fingerprints.contains(fingerprint))
+    {
+        Some(fingerprint) => {
+            log::debug!("Commit {} is signed by trusted key {}", commit, fingerprint);
+            Ok(())
+        }
+        None => bail!(
+            "Commit {} is signed by key {} which is not one of the trusted keys",
+            commit,
+            fingerprints
+                .first()
+                .map(String::as_str)
+                .unwrap_or("unknown")
+        ),
+    }
+}
+
+/// Verify the signature of *commit* and, if *last_verified* is given, of all commits since that one
+///
+/// Commits since *last_verified* are all commits which are reachable from *commit* but not from *last_verified*.
+/// *commit* itself is always verified, even if it is an ancestor of *last_verified*.
+/// *last_verified* should be an ancestor or descendant of *commit* because otherwise all commits since their last
+/// common ancestor are verified.
+pub(crate) fn verify_commits(
+    repo: &Repository,
+    commit: Oid,
+    last_verified: Option<Oid>,
+    trusted_fingerprints: &[String],
+) -> anyhow::Result<()> {
+    let trusted_fingerprints = trusted_fingerprints
+        .iter()
+        .map(|fingerprint| normalize_fingerprint(fingerprint))
+        .collect::<Vec<_>>();
+
+    verify_commit(repo, commit, &trusted_fingerprints)?;
+    if let Some(last_verified) = last_verified {
+        let mut revwalk = repo.revwalk()?;
+        revwalk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+        revwalk.push(commit)?;
+        revwalk.hide(last_verified)?;
+        for i_commit in revwalk {
+            let i_commit = i_commit?;
+            if i_commit != commit {
+                verify_commit(repo, i_commit, &trusted_fingerprints)?;
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,148 @@
+//! Configuration of this plugin that is given through environment variables
+//!
+//! Since the plugin is executed by kustomize, environment variables are the only way to configure its behavior
+//! independently of the processed manifests.
+use anyhow::Context;
+use std::env;
+use std::time::Duration;
+
+/// Environment variable which configures how long a fetched git source is considered fresh
+///
+/// The value is a human readable duration like `10m` or `1h 30m`.
+pub(crate) const FETCH_TTL_ENV: &str = "KUSTOMIZE_PASS_FETCH_TTL";
+
+/// Environment variable which disables all network access if set to a truthy value
+pub(crate) const OFFLINE_ENV: &str = "KUSTOMIZE_PASS_OFFLINE";
+
+/// Environment variable which names an ssh identity file that is used for git sources without an explicit `sshKey`
+pub(crate) const SSH_KEY_ENV: &str = "KUSTOMIZE_PASS_SSH_KEY";
+
+/// Environment variable which configures how long to wait for a cached clone that is used by another process
+///
+/// The value is a human readable duration like `30s` or `5m`.
+pub(crate) const LOCK_TIMEOUT_ENV: &str = "KUSTOMIZE_PASS_LOCK_TIMEOUT";
+
+/// Environment variable which selects the git backend for sources that do not configure one
+pub(crate) const GIT_BACKEND_ENV: &str = "KUSTOMIZE_PASS_GIT_BACKEND";
+
+/// Environment variable which contains url overrides for git sources
+///
+/// The value is a comma separated list of `<url prefix>=<replacement>` entries.
+pub(crate) const URL_OVERRIDES_ENV: &str = "KUSTOMIZE_PASS_URL_OVERRIDES";
+
+/// Environment variable which configures how long to wait for a connection to a git remote
+///
+/// The value is a human readable duration like `10s`. Zero disables the timeout.
+pub(crate) const CONNECT_TIMEOUT_ENV: &str = "KUSTOMIZE_PASS_CONNECT_TIMEOUT";
+
+/// Environment variable which configures how long a transfer from a git remote may stall before it is aborted
+///
+/// The value is a human readable duration like `1m`. Zero disables the timeout.
+pub(crate) const TRANSFER_TIMEOUT_ENV: &str = "KUSTOMIZE_PASS_TRANSFER_TIMEOUT";
+
+/// Environment variable which configures how often a failed clone or fetch is retried
+pub(crate) const FETCH_RETRIES_ENV: &str = "KUSTOMIZE_PASS_FETCH_RETRIES";
+
+/// Whether the given environment variable is set to a value like `1`, `true` or `yes`
+fn is_truthy(name: &str) -> bool {
+    env::var(name)
+        .map(|value| matches!(value.to_lowercase().as_str(), "1" | "true" | "yes" | "on"))
+        .unwrap_or(false)
+}
+
+/// How long a fetched git source is considered fresh so that it is not fetched again
+///
+/// Defaults to zero which means that git sources are fetched every time.
+pub(crate) fn fetch_ttl() -> anyhow::Result<Duration> {
+    match env::var(FETCH_TTL_ENV) {
+        Err(_) => Ok(Duration::ZERO),
+        Ok(value) => humantime::parse_duration(&value).context(format!(
+            "Environment variable {FETCH_TTL_ENV} contains the invalid duration {value}"
+        )),
+    }
+}
+
+/// How long to wait for a cached clone that is locked by another process
+///
+/// Defaults to two minutes.
+pub(crate) fn lock_timeout() -> anyhow::Result<Duration> {
+    match env::var(LOCK_TIMEOUT_ENV) {
+        Err(_) => Ok(Duration::from_secs(120)),
+        Ok(value) => humantime::parse_duration(&value).context(format!(
+            "Environment variable {LOCK_TIMEOUT_ENV} contains the invalid duration {value}"
+        )),
+    }
+}
+
+/// How long to wait for a connection to a git remote
+///
+/// Defaults to 30 seconds.
+pub(crate) fn connect_timeout() -> anyhow::Result<Duration> {
+    match env::var(CONNECT_TIMEOUT_ENV) {
+        Err(_) => Ok(Duration::from_secs(30)),
+        Ok(value) => humantime::parse_duration(&value).context(format!(
+            "Environment variable {CONNECT_TIMEOUT_ENV} contains the invalid duration {value}"
+        )),
+    }
+}
+
+/// How long a transfer from a git remote may stall without receiving any data
+///
+/// Defaults to one minute.
+pub(crate) fn transfer_timeout() -> anyhow::Result<Duration> {
+    match env::var(TRANSFER_TIMEOUT_ENV) {
+        Err(_) => Ok(Duration::from_secs(60)),
+        Ok(value) => humantime::parse_duration(&value).context(format!(
+            "Environment variable {TRANSFER_TIMEOUT_ENV} contains the invalid duration {value}"
+        )),
+    }
+}
+
+/// How often a failed clone or fetch is retried before the next mirror is tried
+///
+/// Defaults to two retries.
+pub(crate) fn fetch_retries() -> anyhow::Result<u32> {
+    match env::var(FETCH_RETRIES_ENV) {
+        Err(_) => Ok(2),
+        Ok(value) => value.parse().context(format!(
+            "Environment variable {FETCH_RETRIES_ENV} contains the invalid number {value}"
+        )),
+    }
+}
+
+/// Whether the plugin should work without any network access and only use already cached data
+pub(crate) fn offline() -> bool {
+    is_truthy(OFFLINE_ENV)
+}
+
+/// The ssh identity file that should be used for git sources which do not configure one themselves
+pub(crate) fn ssh_key() -> Option<String> {
+    env::var(SSH_KEY_ENV).ok().filter(|value| !value.is_empty())
+}
+
+/// The git backend that should be used for git sources which do not configure one themselves
+pub(crate) fn git_backend() -> Option<String> {
+    env::var(GIT_BACKEND_ENV)
+        .ok()
+        .filter(|value| !value.is_empty())
+}
+
+/// Url overrides for git sources as pairs of url prefix and its replacement
+pub(crate) fn url_overrides() -> anyhow::Result<Vec<(String, String)>> {
+    match env::var(URL_OVERRIDES_ENV) {
+        Err(_) => Ok(Vec::new()),
+        Ok(value) => value
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                entry
+                    .split_once('=')
+                    .map(|(prefix, replacement)| (prefix.to_string(), replacement.to_string()))
+                    .context(format!(
+                        "Environment variable {URL_OVERRIDES_ENV} contains the entry {entry} which is not of the form <url>=<replacement>"
+                    ))
+            })
+            .collect(),
+    }
+}
@@ -1,6 +1,8 @@
-use crate::{V1Beta1PassSecret, V1ResourceList, V1Secret};
-use anyhow::Context;
-use serde_yaml::Value;
+use crate::k8s_types::{K8sTypeId, ResourceRef, V1Beta1PassTransformer};
+use crate::results::{results_from_error, ResultCollector};
+use crate::{placeholder_replacement, secret_conversion, V1Beta1PassSecret, V1ResourceList};
+use anyhow::{anyhow, Context};
+use serde_yaml::{Mapping, Value};
 use std::io::{stdin, stdout, Read};
 
 pub(crate) fn exec_krm_function() -> anyhow::Result<()> {
@@ -13,23 +15,80 @@ pub(crate) fn exec_krm_function() -> anyhow::Result<()> {
 
     // parse read input
     log::debug!("Parsing input from stdin");
-    let input: V1ResourceList =
-        serde_yaml::from_str(&input).context("Could not parse ResourceList from stdin")?;
-    let function_config: V1Beta1PassSecret =
-        serde_yaml::from_value(Value::Mapping(input.clone().function_config.unwrap()))
-            .context("Could not parse function configuration from input ResourceList")?;
+    let input: V1ResourceList = match serde_yaml::from_str(&input) {
+        Ok(input) => input,
+        Err(e) => {
+            let e = anyhow::Error::new(e).context("Could not parse ResourceList from stdin");
+            write_output(&V1ResourceList {
+                results: Some(results_from_error(&e, None)),
+                ..V1ResourceList::new(Vec::new())
+            })?;
+            return Err(e);
+        }
+    };
 
     // construct preliminary output with items copied from input
     let mut output = V1ResourceList::new(input.items);
 
-    // parse function config and handle it by extracting secrets from pass
-    let result: V1Secret = function_config.try_into()?;
-    let result = serde_yaml::to_value(result)?;
-    output.items.push(result);
+    // handle the function config and report errors as well as other reported results in the output
+    let function_config = input.function_config.unwrap_or_default();
+    let mut result_collector = ResultCollector::default();
+    let result = handle_function_config(&function_config, &mut output.items, &mut result_collector);
+    let function_config_ref = ResourceRef::from_resource(&Value::Mapping(function_config));
+    let mut results = result_collector.into_results(function_config_ref.as_ref());
+    if let Err(e) = result {
+        results.extend(results_from_error(&e, function_config_ref.as_ref()));
+        output.results = Some(results);
+        write_output(&output)?;
+        return Err(e);
+    }
 
     // return generated output
-    log::trace!("Generated output:\n{}", serde_yaml::to_string(&output)?);
-    serde_yaml::to_writer(stdout(), &output).context("Could not write results to stdout")?;
+    if !results.is_empty() {
+        output.results = Some(results);
+    }
+    write_output(&output)
+}
+
+/// Handle the function config according to its kind by modifying *items* accordingly
+///
+/// Results which do not prevent the function config from being handled are reported to *results*.
+fn handle_function_config(
+    function_config: &Mapping,
+    items: &mut Vec<Value>,
+    results: &mut ResultCollector,
+) -> anyhow::Result<()> {
+    let function_config = Value::Mapping(function_config.clone());
+    let type_id: K8sTypeId = serde_yaml::from_value(function_config.clone())
+        .context("Could not parse type of function configuration from input ResourceList")?;
+
+    match type_id.kind.as_str() {
+        "PassSecret" => {
+            // parse function config and handle it by extracting secrets from pass
+            let function_config: V1Beta1PassSecret = serde_yaml::from_value(function_config)
+                .context("Could not parse function configuration from input ResourceList")?;
+            let result = secret_conversion::convert_pass_secret(function_config, results)?;
+            let result = serde_yaml::to_value(result)?;
+            items.push(result);
+        }
+        "PassTransformer" => {
+            // parse function config and handle it by replacing placeholders in all items
+            let function_config: V1Beta1PassTransformer = serde_yaml::from_value(function_config)
+                .context("Could not parse function configuration from input ResourceList")?;
+            placeholder_replacement::replace_placeholders(function_config, items, results)?;
+        }
+        kind => {
+            return Err(anyhow!(
+                "Function configuration has unsupported kind {kind}. Only PassSecret and PassTransformer are supported"
+            ))
+        }
+    }
 
     Ok(())
 }
+
+/// Write the output ResourceList to stdout
+fn write_output(output: &V1ResourceList) -> anyhow::Result<()> {
+    log::trace!("Generated output:\n{}", serde_yaml::to_string(output)?);
+    serde_yaml::to_writer(stdout(), output).context("Could not write results to stdout")
+}
@@ -0,0 +1,581 @@
+//! Access to git repositories through the `git` executable of the system
+//!
+//! This is an alternative to the libgit2 based implementation in [`git_util`](crate::git_util) which respects
+//! everything the users git and ssh are configured with (e.g. `~/.ssh/config`, `insteadOf` rules, credential
+//! helpers or `core.sshCommand`).
+use crate::git_util::{
+    is_non_fast_forward, non_fast_forward_error, GitOptions, FETCH_REFSPECS, REMOTE_HEAD,
+    TOKEN_USERNAME,
+};
+use crate::known_hosts::HostKeyError;
+use anyhow::Context;
+use git2::Oid;
+use std::env;
+use std::fmt::{Display, Formatter};
+use std::io::Write;
+use std::path::Path;
+use subprocess::{Exec, ExitStatus, Redirection};
+use tempfile::NamedTempFile;
+
+/// Name of the git executable which is looked up in `PATH`
+const GIT_EXECUTABLE: &str = "git";
+
+/// Environment variable through which git is told how to run ssh
+const GIT_SSH_COMMAND_ENV: &str = "GIT_SSH_COMMAND";
+
+/// Quote *value* so that it is passed as a single word to a shell
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Messages of git, ssh and curl which mean that the credentials were missing or rejected
+const AUTH_FAILURE_MARKERS: [&str; 7] = [
+    "Authentication failed",
+    "Permission denied",
+    "could not read Username",
+    "could not read Password",
+    "terminal prompts disabled",
+    "returned error: 401",
+    "returned error: 403",
+];
+
+/// Messages of git, ssh and curl which mean that the remote could not be reached or the transfer broke off
+const NETWORK_FAILURE_MARKERS: [&str; 12] = [
+    "Could not resolve host",
+    "Could not resolve hostname",
+    "Failed to connect",
+    "Connection refused",
+    "Connection reset",
+    "Connection timed out",
+    "Network is unreachable",
+    "Operation timed out",
+    "Operation too slow",
+    "early EOF",
+    "the remote end hung up unexpectedly",
+    "RPC failed",
+];
+
+/// Why a git command failed
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum FailureKind {
+    /// Credentials for the remote were missing or rejected
+    Auth,
+    /// The remote could not be reached or the connection to it broke off
+    Network,
+    /// Any other reason
+    Other,
+}
+
+/// A git command which exited unsuccessfully
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct CommandError {
+    /// Description of the command
+    description: String,
+    /// How the command exited
+    exit_status: ExitStatus,
+    /// The output of the command on stderr
+    stderr: String,
+    /// Why the command failed according to its output
+    pub(crate) kind: FailureKind,
+}
+
+impl CommandError {
+    /// Describe the failure of the command with *description* from its *exit_status* and *stderr*
+    fn new(description: &str, exit_status: ExitStatus, stderr: &str) -> Self {
+        let contains_any = |markers: &[&str]| markers.iter().any(|marker| stderr.contains(marker));
+        // rejected credentials often cause the connection to be closed, so they are checked first
+        let kind = if contains_any(&AUTH_FAILURE_MARKERS) {
+            FailureKind::Auth
+        } else if contains_any(&NETWORK_FAILURE_MARKERS) {
+            FailureKind::Network
+        } else {
+            FailureKind::Other
+        };
+        Self {
+            description: description.to_string(),
+            exit_status,
+            stderr: stderr.trim().to_string(),
+            kind,
+        }
+    }
+}
+
+impl Display for CommandError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} failed with {:?}: {}",
+            self.description, self.exit_status, self.stderr
+        )
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+/// Run *cmd* while capturing its output and return its stdout
+///
+/// The output must be captured because stdout of this program is reserved for the output ResourceList.
+fn run(cmd: Exec, description: &str) -> anyhow::Result<String> {
+    log::trace!("Executing {}", cmd.to_cmdline_lossy());
+    let capture = cmd
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Pipe)
+        .capture()
+        .context(format!("Could not execute {GIT_EXECUTABLE}"))?;
+    if !capture.stderr.is_empty() {
+        log::trace!("git stderr: {}", capture.stderr_str());
+    }
+    if !capture.success() {
+        return Err(match host_key_error(&capture.stderr_str()) {
+            // the warnings of ssh are fully described by the error
+            Some(e) => anyhow::Error::new(e).context(format!("{description} failed")),
+            None => {
+                CommandError::new(description, capture.exit_status, &capture.stderr_str()).into()
+            }
+        });
+    }
+    Ok(capture.stdout_str())
+}
+
+/// Create a git command that operates on the repository at *path*
+fn git(path: &Path) -> Exec {
+    Exec::cmd(GIT_EXECUTABLE).arg("-C").arg(path)
+}
+
+/// The ssh command which git would use for the repository at *path* on its own
+///
+/// This is `GIT_SSH_COMMAND`, `core.sshCommand` or plain `ssh`.
+fn configured_ssh_command(path: &Path) -> String {
+    env::var(GIT_SSH_COMMAND_ENV)
+        .ok()
+        .or_else(|| {
+            run(
+                git(path).args(&["config", "--get", "core.sshCommand"]),
+                "git config",
+            )
+            .ok()
+        })
+        .map(|command| command.trim().to_string())
+        .filter(|command| !command.is_empty())
+        .unwrap_or_else(|| "ssh".to_string())
+}
+
+/// Interpret the *stderr* of a failed git command as ssh host key verification failure if it is one
+fn host_key_error(stderr: &str) -> Option<HostKeyError> {
+    let find_between = |prefix: &str, suffix: &str| {
+        stderr.lines().find_map(|line| {
+            let (_, remainder) = line.split_once(prefix)?;
+            let (value, _) = remainder.split_once(suffix)?;
+            Some(value.to_string())
+        })
+    };
+    let key_type = || {
+        find_between("The fingerprint for the ", " key sent by the remote host")
+            .unwrap_or_else(|| "unknown".to_string())
+    };
+
+    if let Some((key_type, host)) =
+        find_between("The ", " is marked as revoked").and_then(|revoked| {
+            let (key_type, host) = revoked.split_once(" host key for ")?;
+            Some((key_type.to_string(), host.to_string()))
+        })
+    {
+        Some(HostKeyError::Revoked { host, key_type })
+    } else if let Some(host) = find_between("Host key for ", " has changed") {
+        Some(HostKeyError::Mismatch {
+            host,
+            key_type: key_type(),
+        })
+    } else {
+        find_between(
+            " host key is known for ",
+            " and you have requested strict checking",
+        )
+        .map(|host| HostKeyError::Unknown { host })
+    }
+}
+
+/// Access to the remote of a repository with everything configured that *options* require
+///
+/// ssh is run through `GIT_SSH_COMMAND` with the ssh key and known hosts of *options* appended to the command that
+/// git would use otherwise.
+/// The configured command is kept as it is so that quoted programs and wrappers still work and git still detects
+/// the ssh variant from it.
+/// Because ssh uses the first value it is given for an option, the appended options take precedence over the ssh
+/// configuration but not over options of the configured command itself.
+/// Host keys are always checked strictly so that unknown hosts are rejected in the same way as by libgit2.
+/// A token from the environment variable of *options* is passed to git through a credential helper which reads the
+/// variable itself so that the token never appears on a command line.
+/// Http transfers are aborted when they stall for longer than the transfer timeout of *options*, while connect
+/// timeouts are left to the configuration of git and ssh.
+struct RemoteAccess<'a> {
+    /// Path of the repository
+    path: &'a Path,
+
+    /// Options for accessing the remote
+    options: &'a GitOptions,
+
+    /// Shell command which git uses to run ssh
+    ssh_command: String,
+
+    /// Temporary known_hosts file which is referenced by the ssh command and needs to live as long as it
+    _known_hosts_file: Option<NamedTempFile>,
+}
+
+impl<'a> RemoteAccess<'a> {
+    /// Configure access to the remote of the repository at *path* as required by *options*
+    fn new(path: &'a Path, options: &'a GitOptions) -> anyhow::Result<Self> {
+        let mut known_hosts_file = None;
+        let mut ssh_args = vec!["-o StrictHostKeyChecking=yes".to_string()];
+        if let Some(ssh_key) = &options.ssh_key {
+            ssh_args.push(format!("-i {}", shell_quote(&ssh_key.to_string_lossy())));
+        }
+        if let Some(known_hosts) = &options.known_hosts {
+            let mut file = NamedTempFile::new().context("Could not create known_hosts file")?;
+            file.write_all(known_hosts.as_bytes())
+                .context("Could not write known_hosts file")?;
+            ssh_args.push(format!(
+                "-o {}",
+                shell_quote(&format!(
+                    "UserKnownHostsFile={} ~/.ssh/known_hosts",
+                    file.path().display()
+                ))
+            ));
+            known_hosts_file = Some(file);
+        }
+
+        Ok(Self {
+            path,
+            options,
+            ssh_command: format!("{} {}", configured_ssh_command(path), ssh_args.join(" ")),
+            _known_hosts_file: known_hosts_file,
+        })
+    }
+
+    /// Create a git command that operates on the repository and may access its remote
+    fn git(&self) -> Exec {
+        let mut git = git(self.path).env(GIT_SSH_COMMAND_ENV, &self.ssh_command);
+        if !self.options.transfer_timeout.is_zero() {
+            git = git
+                .arg("-c")
+                .arg("http.lowSpeedLimit=1")
+                .arg("-c")
+                .arg(format!(
+                    "http.lowSpeedTime={}",
+                    self.options.transfer_timeout.as_secs().max(1)
+                ));
+        }
+        if let Some(token_env) = &self.options.token_env {
+            // the empty helper removes all previously configured helpers so that the token is used
+            git = git
+                .arg("-c")
+                .arg("credential.helper=")
+                .arg("-c")
+                .arg(format!(
+                    "credential.helper=!f() {{ test \"$1\" = get && echo \"password=${token_env}\"; }}; f"
+                ))
+                .arg("-c")
+                .arg(format!("credential.username={TOKEN_USERNAME}"));
+        }
+        git
+    }
+}
+
+/// Do what `git fetch` would do for all branches and tags of the repository at *path*
+///
+/// Afterwards, `refs/remotes/origin/HEAD` points to the default branch of the remote.
+/// The remote is accessed as described in [`RemoteAccess`].
+pub(crate) fn do_git_fetch(path: &Path, options: &GitOptions) -> anyhow::Result<()> {
+    log::debug!("Fetching new content for repository using the git executable");
+
+    let remote = RemoteAccess::new(path, options)?;
+    let mut fetch = remote.git().args(&["fetch", "--quiet"]);
+    if let Some(depth) = options.depth {
+        fetch = fetch.arg(format!("--depth={depth}"));
+    }
+    run(fetch.arg("origin").args(&FETCH_REFSPECS), "git fetch")?;
+
+    // git fetch does not record the default branch of the remote so it is queried separately
+    let remote_head = run(
+        remote
+            .git()
+            .args(&["ls-remote", "--symref", "origin", "HEAD"]),
+        "git ls-remote",
+    )?;
+    drop(remote);
+    let default_branch = match remote_head
+        .lines()
+        .filter_map(|line| line.strip_prefix("ref: refs/heads/"))
+        .find_map(|line| line.split_whitespace().next())
+    {
+        Some(default_branch) => Some(default_branch.to_string()),
+        None => guess_default_branch(path, &remote_head)?,
+    };
+    log::trace!("Default branch of remote is {:?}", default_branch);
+    if let Some(default_branch) = default_branch {
+        run(
+            git(path)
+                .args(&["symbolic-ref", REMOTE_HEAD])
+                .arg(format!("refs/remotes/origin/{default_branch}")),
+            "git symbolic-ref",
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Push *commit* to *branch* of the remote at *url* of the repository at *path*
+///
+/// The remote is accessed as described in [`RemoteAccess`].
+/// A push which is rejected because it is not a fast-forward results in a [`non_fast_forward_error`].
+pub(crate) fn do_git_push(
+    path: &Path,
+    url: &str,
+    branch: &str,
+    commit: Oid,
+    options: &GitOptions,
+) -> anyhow::Result<()> {
+    log::debug!(
+        "Pushing commit {} to branch {} of {} using the git executable",
+        commit,
+        branch,
+        url
+    );
+
+    let remote = RemoteAccess::new(path, options)?;
+    match run(
+        remote
+            .git()
+            .args(&["push", "--quiet"])
+            .arg(url)
+            .arg(format!("{commit}:refs/heads/{branch}")),
+        "git push",
+    ) {
+        Err(e) if is_non_fast_forward(&e.to_string()) => Err(non_fast_forward_error(url, branch)),
+        result => result.map(|_| ()),
+    }
+}
+
+/// Guess the default branch of a remote which does not advertise it (e.g. a bundle) from the *remote_head* output
+/// of `git ls-remote`
+///
+/// Like `git clone`, this picks a fetched branch which points to the same commit as the remotes `HEAD`.
+fn guess_default_branch(path: &Path, remote_head: &str) -> anyhow::Result<Option<String>> {
+    let head_commit = match remote_head
+        .lines()
+        .find_map(|line| line.strip_suffix("\tHEAD"))
+    {
+        None => return Ok(None),
+        Some(head_commit) => head_commit,
+    };
+    let branches = run(
+        git(path)
+            .args(&[
+                "for-each-ref",
+                "--format=%(refname:lstrip=3)",
+                "--points-at",
+            ])
+            .arg(head_commit)
+            .arg("refs/remotes/origin/"),
+        "git for-each-ref",
+    )?;
+    Ok(branches
+        .lines()
+        .find(|branch| *branch != "HEAD")
+        .map(str::to_string))
+}
+
+/// Turn *path* into a sparse-checkout pattern which matches exactly this path relative to the repository root
+///
+/// Patterns follow the rules of `.gitignore`, so wildcards and spaces are escaped.
+fn sparse_checkout_pattern(path: &str) -> String {
+    let mut pattern = String::from("/");
+    for c in path.chars() {
+        if matches!(c, '\\' | '*' | '?' | '[' | ' ') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern
+}
+
+/// Check out exactly the given *commit* of the repository at *path* as detached `HEAD`
+///
+/// Local modifications and untracked files are discarded.
+/// If *sparse_paths* are given, only files below these paths are checked out, so nothing is checked out if there
+/// are none.
+pub(crate) fn do_git_checkout(
+    path: &Path,
+    commit: Oid,
+    sparse_paths: Option<&[String]>,
+) -> anyhow::Result<()> {
+    log::debug!("Checking out commit {} using the git executable", commit);
+
+    let sparse_checkout = git(path).arg("sparse-checkout");
+    let sparse_checkout = match sparse_paths {
+        None => sparse_checkout.arg("disable"),
+        // git treats an empty set of patterns as all files at the root of the repository
+        Some([]) => sparse_checkout.args(&["set", "--no-cone", "!/*"]),
+        Some(sparse_paths) => sparse_checkout.args(&["set", "--no-cone"]).args(
+            &sparse_paths
+                .iter()
+                .map(|sparse_path| sparse_checkout_pattern(sparse_path))
+                .collect::<Vec<_>>(),
+        ),
+    };
+    run(sparse_checkout, "git sparse-checkout")?;
+    run(
+        git(path)
+            .args(&["checkout", "--quiet", "--force", "--detach"])
+            .arg(commit.to_string()),
+        "git checkout",
+    )?;
+    run(git(path).args(&["clean", "--quiet", "-ffd"]), "git clean")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Explanation which git prints after ssh failed
+    const SSH_FAILURE: &str = "fatal: Could not read from remote repository.\n\n\
+        Please make sure you have the correct access rights\nand the repository exists.\n";
+
+    #[test]
+    fn sparse_paths_are_matched_literally() {
+        assert_eq!(sparse_checkout_pattern("app/db.gpg"), "/app/db.gpg");
+        assert_eq!(sparse_checkout_pattern("secrets"), "/secrets");
+        assert_eq!(
+            sparse_checkout_pattern(r"!a/#b/c*d?[e] f\g.gpg"),
+            r"/!a/#b/c\*d\?\[e]\ f\\g.gpg"
+        );
+    }
+
+    #[test]
+    fn host_key_errors_are_recognized() {
+        let cases = [
+            (
+                format!(
+                    "No ED25519 host key is known for git.example.com and you have requested strict checking.\n\
+                    Host key verification failed.\n{SSH_FAILURE}"
+                ),
+                Some(HostKeyError::Unknown {
+                    host: "git.example.com".to_string(),
+                }),
+            ),
+            (
+                format!(
+                    "@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@\n\
+                    @    WARNING: REMOTE HOST IDENTIFICATION HAS CHANGED!     @\n\
+                    @@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@\n\
+                    IT IS POSSIBLE THAT SOMEONE IS DOING SOMETHING NASTY!\n\
+                    Someone could be eavesdropping on you right now (man-in-the-middle attack)!\n\
+                    It is also possible that a host key has just been changed.\n\
+                    The fingerprint for the ED25519 key sent by the remote host is\n\
+                    SHA256:+DiY3wvvV6TuJJhbpZisF/zLDA0zPMSvHdkr4UvCOqU.\n\
+                    Please contact your system administrator.\n\
+                    Add correct host key in /root/.ssh/known_hosts to get rid of this message.\n\
+                    Offending ED25519 key in /root/.ssh/known_hosts:3\n\
+                    Host key for [git.example.com]:2222 has changed and you have requested strict checking.\n\
+                    Host key verification failed.\n{SSH_FAILURE}"
+                ),
+                Some(HostKeyError::Mismatch {
+                    host: "[git.example.com]:2222".to_string(),
+                    key_type: "ED25519".to_string(),
+                }),
+            ),
+            (
+                format!(
+                    "@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@\n\
+                    @       WARNING: REVOKED HOST KEY DETECTED!               @\n\
+                    @@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@\n\
+                    The ECDSA host key for git.example.com is marked as revoked.\n\
+                    This could mean that a stolen key is being used to\n\
+                    impersonate this host.\n\
+                    Host key verification failed.\n{SSH_FAILURE}"
+                ),
+                Some(HostKeyError::Revoked {
+                    host: "git.example.com".to_string(),
+                    key_type: "ECDSA".to_string(),
+                }),
+            ),
+            (
+                format!("git@git.example.com: Permission denied (publickey).\n{SSH_FAILURE}"),
+                None,
+            ),
+            (
+                "fatal: couldn't find remote ref refs/heads/missing\n".to_string(),
+                None,
+            ),
+        ];
+        for (stderr, expected) in cases {
+            assert_eq!(host_key_error(&stderr), expected, "{stderr}");
+        }
+    }
+
+    #[test]
+    fn failures_are_classified_by_stderr() {
+        let cases = [
+            (
+                format!("git@git.example.com: Permission denied (publickey).\n{SSH_FAILURE}"),
+                FailureKind::Auth,
+            ),
+            (
+                "remote: HTTP Basic: Access denied\n\
+                fatal: Authentication failed for 'https://git.example.com/app.git/'\n"
+                    .to_string(),
+                FailureKind::Auth,
+            ),
+            (
+                "fatal: could not read Username for 'https://git.example.com': terminal prompts disabled\n"
+                    .to_string(),
+                FailureKind::Auth,
+            ),
+            (
+                "fatal: unable to access 'https://git.example.com/app.git/': The requested URL returned error: 403\n"
+                    .to_string(),
+                FailureKind::Auth,
+            ),
+            (
+                "fatal: unable to access 'https://nowhere.invalid/x.git/': Could not resolve host: nowhere.invalid\n"
+                    .to_string(),
+                FailureKind::Network,
+            ),
+            (
+                format!(
+                    "ssh: Could not resolve hostname nowhere.invalid: Name or service not known\n{SSH_FAILURE}"
+                ),
+                FailureKind::Network,
+            ),
+            (
+                format!("ssh: connect to host 127.0.0.1 port 1: Connection refused\n{SSH_FAILURE}"),
+                FailureKind::Network,
+            ),
+            (
+                "error: RPC failed; curl 18 transfer closed with outstanding read data remaining\n\
+                fatal: early EOF\nfatal: fetch-pack: invalid index-pack output\n"
+                    .to_string(),
+                FailureKind::Network,
+            ),
+            (
+                "fatal: unable to access 'https://git.example.com/app.git/': \
+                Operation too slow. Less than 1 bytes/sec transferred the last 60 seconds\n"
+                    .to_string(),
+                FailureKind::Network,
+            ),
+            (
+                "fatal: couldn't find remote ref refs/heads/missing\n".to_string(),
+                FailureKind::Other,
+            ),
+            (
+                "fatal: 'origin' does not appear to be a git repository\n".to_string(),
+                FailureKind::Other,
+            ),
+        ];
+        for (stderr, expected) in cases {
+            let error = CommandError::new("git fetch", ExitStatus::Exited(128), &stderr);
+            assert_eq!(error.kind, expected, "{stderr}");
+        }
+    }
+}
@@ -1,10 +1,22 @@
-use anyhow::{anyhow, Context};
+use crate::known_hosts::{self, HostKeyError, KnownHosts};
+use crate::{cache, commit_signatures, env_config, git_cli, netrc};
+use anyhow::{anyhow, bail, Context};
 use directories::UserDirs;
-use git2::build::{CheckoutBuilder, RepoBuilder};
-use git2::{BranchType, Config, Cred, CredentialType, FetchOptions, RemoteCallbacks, Repository};
-use std::env;
-use std::path::Path;
+use git2::build::CheckoutBuilder;
+use git2::cert::Cert;
+use git2::{
+    CertificateCheckStatus, Config, Cred, CredentialType, Delta, DiffOptions, FetchOptions, Oid,
+    Progress, PushOptions, RemoteCallbacks, Repository, Signature,
+};
+use std::cell::RefCell;
+use std::fmt::{Display, Formatter};
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use std::{env, fs, process, thread};
 use subprocess::Exec;
+use url::Url;
 
 /// prompt the askpass program given by *exe* for *field* of the given *url*.
 fn prompt_git_askpass(exe: &str, field: &str, url: &str) -> Result<String, git2::Error> {
@@ -42,15 +54,64 @@ fn prompt_git_askpass(exe: &str, field: &str, url: &str) -> Result<String, git2:
         .to_string())
 }
 
+/// User name which is used together with a token if the url does not contain one
+pub(crate) const TOKEN_USERNAME: &str = "x-access-token";
+
+/// Try the different sources of username/password credentials in the same order as git
+///
+/// A token from the environment variable given by *options* and the `~/.netrc` file take precedence.
+/// Only the source of the credentials is logged, never the credentials themselves.
 fn create_username_password_credentials(
     url: &str,
     username_from_url: Option<&str>,
+    options: &GitOptions,
 ) -> Result<Cred, git2::Error> {
+    // token from the configured environment variable
+    if let Some(token_env) = &options.token_env {
+        match env::var(token_env) {
+            Ok(token) => {
+                log::debug!(
+                    "Using token from environment variable {} as credentials for {}",
+                    token_env,
+                    url
+                );
+                return Cred::userpass_plaintext(
+                    username_from_url.unwrap_or(TOKEN_USERNAME),
+                    &token,
+                );
+            }
+            Err(_) => log::warn!(
+                "Environment variable {} which should contain a token for {} is not set",
+                token_env,
+                url
+            ),
+        }
+    }
+
+    // credentials from ~/.netrc
+    let host = Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string));
+    if let Some(credentials) = host.as_deref().and_then(netrc::lookup) {
+        log::debug!("Using credentials from ~/.netrc as credentials for {}", url);
+        return Cred::userpass_plaintext(
+            credentials
+                .login
+                .as_deref()
+                .or(username_from_url)
+                .unwrap_or_default(),
+            &credentials.password,
+        );
+    }
+
     let git_config = Config::open_default()?;
 
     // credentials from credential-helper
     log::debug!("Trying to use git credentials from credential helper");
     let mut creds = Cred::credential_helper(&git_config, url, username_from_url);
+    if creds.is_ok() {
+        log::debug!("Using credentials from credential helper for {}", url);
+    }
 
     // credentials from GIT_ASKPASS environment variable
     if let Ok(git_askpass) = env::var("GIT_ASKPASS") {
@@ -83,34 +144,315 @@ fn create_username_password_credentials(
     creds
 }
 
-fn create_ssh_credentials(username_from_url: Option<&str>) -> Result<Cred, git2::Error> {
-    // ssh key from agent or from ~/.ssh/id_rsa
-    log::debug!("Trying to retrieve git credentials from ssh agent");
+/// Names of the identity files in `~/.ssh` which are tried in order, the same way ssh does
+const DEFAULT_SSH_IDENTITIES: [&str; 3] = ["id_ed25519", "id_ecdsa", "id_rsa"];
 
-    Cred::ssh_key_from_agent(username_from_url.unwrap_or("git")).or_else(|e| {
-        log::debug!("Could not retrieve credentials from ssh agent: {}", e);
-        log::debug!("Trying to retrieve use the ssh key at ~/.ssh/id_rsa instead");
-        let dirs = UserDirs::new().unwrap();
-        Cred::ssh_key(
-            username_from_url.unwrap_or("git"),
-            None,
-            &dirs.home_dir().join(".ssh").join("id_rsa"),
-            None,
-        )
-    })
+/// Options which influence how repositories are accessed
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub(crate) struct GitOptions {
+    /// Whether the `git` executable should be used instead of libgit2 for fetching and checking out
+    pub(crate) use_git_executable: bool,
+
+    /// Identity file which is tried before all other ssh identities
+    pub(crate) ssh_key: Option<PathBuf>,
+
+    /// Entries in known_hosts format which are trusted in addition to the known_hosts files
+    pub(crate) known_hosts: Option<String>,
+
+    /// Number of commits from the tip of each branch and tag which are fetched or none for the complete history
+    pub(crate) depth: Option<u32>,
+
+    /// Name of an environment variable which contains a token for authenticating against https remotes
+    pub(crate) token_env: Option<String>,
+
+    /// How long a transfer may stall without receiving data or zero to wait indefinitely
+    pub(crate) transfer_timeout: Duration,
+
+    /// How often a failed fetch is retried
+    pub(crate) retries: u32,
+}
+
+/// Fetch depth which fetches the complete history of a shallow clone
+///
+/// Both git and libgit2 treat this depth as infinite.
+pub(crate) const UNSHALLOW_DEPTH: u32 = i32::MAX as u32;
+
+/// An ssh identity which can be used for authenticating against a remote
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum SshIdentity {
+    /// Any key that is provided by the running ssh agent
+    Agent,
+    /// The private key stored in the given file
+    File(PathBuf),
 }
 
-/// Create git2 fetch options the way it is needed
+/// All ssh identities that should be tried in order
+///
+/// These are the configured identity file, the ssh agent and the default identity files which exist in `~/.ssh`.
+fn ssh_identities(options: &GitOptions) -> Vec<SshIdentity> {
+    let mut identities = Vec::new();
+    if let Some(ssh_key) = &options.ssh_key {
+        identities.push(SshIdentity::File(ssh_key.to_owned()));
+    }
+    identities.push(SshIdentity::Agent);
+    if let Some(dirs) = UserDirs::new() {
+        identities.extend(
+            DEFAULT_SSH_IDENTITIES
+                .iter()
+                .map(|name| dirs.home_dir().join(".ssh").join(name))
+                .filter(|path| path.is_file())
+                .map(SshIdentity::File),
+        );
+    }
+    identities
+}
+
+/// Whether the private key in *content* is protected by a passphrase
+///
+/// Both the PEM format and the newer OpenSSH format (which encodes its cipher inside the key data) are detected.
+fn is_encrypted_ssh_key(content: &str) -> bool {
+    if content.contains("ENCRYPTED") {
+        return true;
+    }
+
+    let key_data = content
+        .lines()
+        .filter(|line| !line.starts_with("-----"))
+        .collect::<String>();
+    base64::decode(key_data)
+        .ok()
+        .and_then(|key_data| {
+            // the magic is followed by the length prefixed name of the cipher which is "none" for plain keys
+            key_data
+                .strip_prefix(b"openssh-key-v1\0")
+                .and_then(|rest| rest.get(4..))
+                .map(|cipher_name| !cipher_name.starts_with(b"none"))
+        })
+        .unwrap_or(false)
+}
+
+/// Retrieve the passphrase of the ssh key at *path* from the askpass program given by `GIT_ASKPASS` or `SSH_ASKPASS`
+fn prompt_ssh_passphrase(path: &Path) -> Result<String, git2::Error> {
+    let askpass = env::var("GIT_ASKPASS")
+        .or_else(|_| env::var("SSH_ASKPASS"))
+        .map_err(|_| {
+            git2::Error::new(
+                git2::ErrorCode::Auth,
+                git2::ErrorClass::Callback,
+                format!(
+                    "ssh key {} is protected by a passphrase but neither GIT_ASKPASS nor SSH_ASKPASS is set",
+                    path.display()
+                ),
+            )
+        })?;
+
+    log::debug!(
+        "Retrieving passphrase of ssh key {} from askpass program",
+        path.display()
+    );
+    prompt_git_askpass(&askpass, "Passphrase", &path.display().to_string())
+}
+
+fn create_ssh_credentials(
+    identity: &SshIdentity,
+    username_from_url: Option<&str>,
+) -> Result<Cred, git2::Error> {
+    let username = username_from_url.unwrap_or("git");
+    match identity {
+        SshIdentity::Agent => {
+            log::debug!("Trying to retrieve git credentials from ssh agent");
+            Cred::ssh_key_from_agent(username)
+        }
+        SshIdentity::File(path) => {
+            log::debug!("Trying to use the ssh key at {}", path.display());
+            let content = fs::read_to_string(path).map_err(|e| {
+                git2::Error::from_str(&format!("Could not read ssh key {}: {}", path.display(), e))
+            })?;
+            let passphrase = if is_encrypted_ssh_key(&content) {
+                Some(prompt_ssh_passphrase(path)?)
+            } else {
+                None
+            };
+
+            let mut public_key_path = path.to_owned().into_os_string();
+            public_key_path.push(".pub");
+            let public_key_path = PathBuf::from(public_key_path);
+            Cred::ssh_key(
+                username,
+                Some(public_key_path.as_path()).filter(|path| path.is_file()),
+                path,
+                passphrase.as_deref(),
+            )
+        }
+    }
+}
+
+/// The port which is used when connecting to *url* via ssh
+fn ssh_port(url: &str) -> u16 {
+    // scp-like urls such as git@example.com:repo.git cannot specify a port
+    Url::parse(url)
+        .ok()
+        .and_then(|url| url.port())
+        .unwrap_or(known_hosts::DEFAULT_SSH_PORT)
+}
+
+/// Verify the ssh host key given by *cert* against *known_hosts*
+///
+/// Certificates of other transports are left to the default verification of libgit2.
+fn check_host_key(
+    cert: &Cert,
+    host: &str,
+    port: u16,
+    known_hosts: &KnownHosts,
+) -> Result<CertificateCheckStatus, HostKeyError> {
+    let host_key = match cert.as_hostkey() {
+        None => return Ok(CertificateCheckStatus::CertificatePassthrough),
+        Some(host_key) => host_key,
+    };
+    let key_type = host_key.hostkey_type().map(|key_type| key_type.name());
+
+    match (host_key.hostkey(), host_key.hash_sha256()) {
+        (Some(raw_key), _) => known_hosts.verify(host, port, key_type, |key| key == raw_key),
+        (None, Some(sha256)) => known_hosts.verify(host, port, key_type, |key| {
+            known_hosts::key_sha256(key) == *sha256
+        }),
+        // without any information about the key it cannot be known
+        (None, None) => Err(HostKeyError::Unknown {
+            host: host.to_string(),
+        }),
+    }?;
+    Ok(CertificateCheckStatus::CertificateOk)
+}
+
+/// Minimum time between two updates of the transfer progress
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Reports the progress of a transfer to stderr in a similar way as git does
+#[derive(Debug, Default)]
+struct ProgressReporter {
+    /// When the progress was last printed
+    last_report: Option<Instant>,
+
+    /// Whether all objects have been received
+    received: bool,
+
+    /// Whether all deltas have been resolved
+    resolved: bool,
+}
+
+impl ProgressReporter {
+    /// Whether enough time has passed since the last update to print another one
+    fn is_due(&self) -> bool {
+        match self.last_report {
+            None => true,
+            Some(last_report) => last_report.elapsed() >= PROGRESS_INTERVAL,
+        }
+    }
+
+    /// Print *progress* unless it was printed very recently
+    ///
+    /// Receiving objects and resolving deltas are reported on separate lines.
+    fn report(&mut self, progress: &Progress<'_>) {
+        let total_objects = progress.total_objects();
+        if total_objects == 0 || self.resolved {
+            return;
+        }
+
+        if !self.received {
+            let received_objects = progress.received_objects();
+            let complete = received_objects == total_objects;
+            if !complete && !self.is_due() {
+                return;
+            }
+            eprint!(
+                "\rReceiving objects: {:3}% ({}/{}), {}",
+                received_objects * 100 / total_objects,
+                received_objects,
+                total_objects,
+                cache::format_size(progress.received_bytes() as u64)
+            );
+            self.last_report = Some(Instant::now());
+            if !complete {
+                return;
+            }
+            eprintln!(", done.");
+            self.received = true;
+        }
+
+        let total_deltas = progress.total_deltas();
+        if total_deltas > 0 {
+            let indexed_deltas = progress.indexed_deltas();
+            let complete = indexed_deltas == total_deltas;
+            if !complete && !self.is_due() {
+                return;
+            }
+            eprint!(
+                "\rResolving deltas: {:3}% ({}/{})",
+                indexed_deltas * 100 / total_deltas,
+                indexed_deltas,
+                total_deltas
+            );
+            self.last_report = Some(Instant::now());
+            if complete {
+                eprintln!(", done.");
+                self.resolved = true;
+            }
+        }
+    }
+}
+
+/// Create git2 remote callbacks the way they are needed for accessing *url*
 ///
 /// This essentially configures a credential callback that tries to mimic the [behavior of git itself](https://git-scm.com/docs/gitcredentials#_requesting_credentials).
-fn create_fetch_options<'cb>() -> FetchOptions<'cb> {
+/// Since the callback is invoked again whenever authentication fails, each invocation for ssh uses the next
+/// identity from [`ssh_identities`].
+///
+/// Additionally, ssh host keys are verified against the known_hosts files and the known hosts of *options*.
+/// If verification fails, the reason is stored in *host_key_error*.
+///
+/// When stderr is a terminal, the progress of the transfer is reported on it.
+fn create_remote_callbacks<'cb>(
+    url: &str,
+    options: &'cb GitOptions,
+    host_key_error: &'cb RefCell<Option<HostKeyError>>,
+) -> RemoteCallbacks<'cb> {
+    let port = ssh_port(url);
     let mut remote_callbacks = RemoteCallbacks::new();
-    remote_callbacks.credentials(|url, username_from_url, allowed_types| {
+    remote_callbacks.certificate_check(move |cert, host| {
+        let known_hosts = KnownHosts::load(options.known_hosts.as_deref());
+        check_host_key(cert, host, port, &known_hosts).map_err(|e| {
+            let error = git2::Error::new(
+                git2::ErrorCode::Certificate,
+                git2::ErrorClass::Ssh,
+                e.to_string(),
+            );
+            host_key_error.replace(Some(e));
+            error
+        })
+    });
+
+    let mut remaining_ssh_identities = None;
+    remote_callbacks.credentials(move |url, username_from_url, allowed_types| {
+        // ssh identities are tried one after another until there are none left
+        if allowed_types.contains(CredentialType::SSH_KEY) {
+            let identities = remaining_ssh_identities
+                .get_or_insert_with(|| ssh_identities(options).into_iter());
+            for identity in identities {
+                match create_ssh_credentials(&identity, username_from_url) {
+                    Ok(creds) => return Ok(creds),
+                    Err(e) => log::debug!("Could not use ssh identity {:?}: {}", identity, e),
+                }
+            }
+            return Err(git2::Error::new(
+                git2::ErrorCode::Auth,
+                git2::ErrorClass::Ssh,
+                format!("None of the available ssh identities was accepted by {url}"),
+            ));
+        }
+
         // use credentials from appropriate source
         let mut creds = if allowed_types.contains(CredentialType::USER_PASS_PLAINTEXT) {
-            create_username_password_credentials(url, username_from_url)
-        } else if allowed_types.contains(CredentialType::SSH_KEY) {
-            create_ssh_credentials(username_from_url)
+            create_username_password_credentials(url, username_from_url, options)
         } else {
             log::warn!(
                 "Requested key type {:?} is not supported and cannot be supplied. Using default credentials",
@@ -129,60 +471,639 @@ fn create_fetch_options<'cb>() -> FetchOptions<'cb> {
         creds
     });
 
+    if io::stderr().is_terminal() {
+        let mut progress_reporter = ProgressReporter::default();
+        remote_callbacks.transfer_progress(move |progress| {
+            progress_reporter.report(&progress);
+            true
+        });
+    }
+
+    remote_callbacks
+}
+
+/// Create git2 fetch options the way it is needed for accessing *url*
+///
+/// See [`create_remote_callbacks`] for how the remote is accessed.
+fn create_fetch_options<'cb>(
+    url: &str,
+    options: &'cb GitOptions,
+    host_key_error: &'cb RefCell<Option<HostKeyError>>,
+) -> FetchOptions<'cb> {
     let mut fetch_options = FetchOptions::new();
-    fetch_options.remote_callbacks(remote_callbacks);
+    fetch_options.remote_callbacks(create_remote_callbacks(url, options, host_key_error));
+    if let Some(depth) = options.depth {
+        fetch_options.depth(i32::try_from(depth).unwrap_or(i32::MAX));
+    }
 
     fetch_options
 }
 
-/// Do what `git fetch` would do for the main branch
-fn do_git_fetch(repo: &Repository) -> anyhow::Result<()> {
-    log::debug!("Fetching new content for existing repository");
+/// Refspecs which are fetched from the remote so that all branches and tags are available locally
+pub(crate) const FETCH_REFSPECS: [&str; 2] = [
+    "+refs/heads/*:refs/remotes/origin/*",
+    "+refs/tags/*:refs/tags/*",
+];
+
+/// Reference which points to the default branch of the remote
+pub(crate) const REMOTE_HEAD: &str = "refs/remotes/origin/HEAD";
+
+/// A revision of a git repository which should be checked out
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum GitRevision<'a> {
+    /// Whatever branch the remotes `HEAD` points to
+    DefaultBranch,
+    /// The tip of a branch on the remote
+    Branch(&'a str),
+    /// A tag
+    Tag(&'a str),
+    /// A specific commit given by its (possibly abbreviated) hash
+    Commit(&'a str),
+    /// Any branch, tag or other revision that can be resolved by git
+    Ref(&'a str),
+}
+
+impl Display for GitRevision<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GitRevision::DefaultBranch => write!(f, "default branch"),
+            GitRevision::Branch(branch) => write!(f, "branch {branch}"),
+            GitRevision::Tag(tag) => write!(f, "tag {tag}"),
+            GitRevision::Commit(commit) => write!(f, "commit {commit}"),
+            GitRevision::Ref(name) => write!(f, "revision {name}"),
+        }
+    }
+}
+
+/// Resolve the given reference name to the commit it points to
+fn find_commit_by_reference(repo: &Repository, name: &str) -> anyhow::Result<Oid> {
+    Ok(repo
+        .find_reference(name)
+        .context(format!("Reference {name} does not exist in repository"))?
+        .peel_to_commit()
+        .context(format!("Reference {name} does not point to a commit"))?
+        .id())
+}
+
+/// Resolve *revision* to a commit of the already fetched repository
+pub(crate) fn resolve_revision(repo: &Repository, revision: GitRevision) -> anyhow::Result<Oid> {
+    match revision {
+        GitRevision::DefaultBranch => find_commit_by_reference(repo, REMOTE_HEAD)
+            .context("Could not determine the default branch of the remote repository"),
+        GitRevision::Branch(branch) => {
+            find_commit_by_reference(repo, &format!("refs/remotes/origin/{branch}"))
+        }
+        GitRevision::Tag(tag) => find_commit_by_reference(repo, &format!("refs/tags/{tag}")),
+        GitRevision::Commit(commit) => Ok(repo
+            .revparse_single(commit)
+            .context(format!("Commit {commit} does not exist in repository"))?
+            .peel_to_commit()
+            .context(format!("{commit} does not reference a commit"))?
+            .id()),
+        GitRevision::Ref(name) => {
+            find_commit_by_reference(repo, &format!("refs/remotes/origin/{name}"))
+                .or_else(|_| find_commit_by_reference(repo, &format!("refs/tags/{name}")))
+                .or_else(|_| find_commit_by_reference(repo, name))
+                .or_else(|_| {
+                    Ok(repo
+                        .revparse_single(name)
+                        .context(format!("Revision {name} does not exist in repository"))?
+                        .peel_to_commit()?
+                        .id())
+                })
+        }
+    }
+}
+
+/// The directory of *repo* in which git commands can be executed
+fn repo_dir(repo: &Repository) -> &Path {
+    repo.workdir().unwrap_or_else(|| repo.path())
+}
+
+/// Do what `git fetch` would do for all branches and tags of the remote at *url*
+///
+/// The `origin` remote of *repo* is pointed to *url* first so that the same clone can be fetched from different
+/// mirrors of a repository.
+/// Afterwards, `refs/remotes/origin/HEAD` points to the default branch of the remote.
+/// If *options* contain a depth, only that many commits of each branch and tag are fetched.
+/// If the host key of an ssh remote cannot be verified, the returned error is a [`HostKeyError`].
+///
+/// A failed fetch is retried as often as *options* allow while the time between attempts grows exponentially.
+/// Failures which would not go away by trying again, like rejected credentials, are returned immediately.
+pub(crate) fn do_git_fetch(
+    repo: &Repository,
+    url: &str,
+    options: &GitOptions,
+) -> anyhow::Result<()> {
+    if repo.find_remote("origin")?.url() != Some(url) {
+        repo.remote_set_url("origin", url)
+            .context("Could not configure remote of repository")?;
+    }
+
+    let mut attempt = 0;
+    loop {
+        let result = match options.use_git_executable {
+            true => git_cli::do_git_fetch(repo_dir(repo), options),
+            false => fetch_with_libgit2(repo, url, options),
+        };
+        match result {
+            Err(e) if attempt < options.retries && is_retryable(&e) => {
+                let backoff = RETRY_BACKOFF
+                    .saturating_mul(1 << attempt.min(16))
+                    .min(MAX_RETRY_BACKOFF);
+                log::warn!(
+                    "Fetching from {} failed, retrying in {}: {:#}",
+                    url,
+                    humantime::format_duration(backoff),
+                    e
+                );
+                thread::sleep(backoff);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Time to wait before the first retry of a failed fetch
+const RETRY_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Upper bound for the time between two attempts of a fetch
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Whether a fetch that failed with *error* might succeed when it is tried again
+///
+/// Only network failures and timeouts are transient, while e.g. rejected credentials or unknown host keys would
+/// fail in the same way again.
+fn is_retryable(error: &anyhow::Error) -> bool {
+    if let Some(e) = error.downcast_ref::<git_cli::CommandError>() {
+        return e.kind == git_cli::FailureKind::Network;
+    }
+    match error.downcast_ref::<git2::Error>() {
+        None => false,
+        Some(e) => match e.code() {
+            git2::ErrorCode::Timeout => true,
+            git2::ErrorCode::Auth | git2::ErrorCode::Certificate => false,
+            // failed connections are reported as operating system errors
+            _ => matches!(e.class(), git2::ErrorClass::Net | git2::ErrorClass::Os),
+        },
+    }
+}
+
+/// Apply the connect and transfer timeouts from the environment to all network operations of libgit2
+///
+/// The options are global to libgit2, so they are only set once before the first remote is accessed.
+#[allow(unsafe_code)]
+fn configure_libgit2_timeouts() -> anyhow::Result<()> {
+    static CONFIGURED: OnceLock<Result<(), String>> = OnceLock::new();
+
+    let as_millis = |timeout: Duration| i32::try_from(timeout.as_millis()).unwrap_or(i32::MAX);
+    let connect_timeout = as_millis(env_config::connect_timeout()?);
+    let transfer_timeout = as_millis(env_config::transfer_timeout()?);
+    CONFIGURED
+        .get_or_init(|| {
+            // SAFETY: the options must not be changed while libgit2 accesses them on another thread, which cannot
+            // happen because they are only set once, before the first network operation, and repositories are only
+            // accessed from a single thread
+            unsafe {
+                git2::opts::set_server_connect_timeout_in_milliseconds(connect_timeout)
+                    .and_then(|()| git2::opts::set_server_timeout_in_milliseconds(transfer_timeout))
+            }
+            .map_err(|e| e.to_string())
+        })
+        .clone()
+        .map_err(|e| anyhow!(e))
+        .context("Could not configure the timeouts of libgit2")
+}
+
+/// Fetch all branches and tags from *url* into *repo* using libgit2
+fn fetch_with_libgit2(repo: &Repository, url: &str, options: &GitOptions) -> anyhow::Result<()> {
+    log::debug!("Fetching new content for repository from {}", url);
+    configure_libgit2_timeouts()?;
+
+    let mut remote = repo.find_remote("origin")?;
+    let host_key_error = RefCell::new(None);
+    let fetch_result = remote.fetch(
+        &FETCH_REFSPECS,
+        Some(&mut create_fetch_options(url, options, &host_key_error)),
+        None,
+    );
+    if let Some(e) = host_key_error.into_inner() {
+        return Err(e.into());
+    }
+    fetch_result.context("Could not fetch from remote repository")?;
+
+    // the default branch is advertised by the remote and remains available after the fetch is done
+    let default_branch = remote.default_branch().ok().and_then(|name| {
+        name.as_str()
+            .and_then(|name| name.strip_prefix("refs/heads/"))
+            .map(str::to_string)
+    });
+    log::trace!("Default branch of remote is {:?}", default_branch);
+    if let Some(default_branch) = default_branch {
+        repo.reference_symbolic(
+            REMOTE_HEAD,
+            &format!("refs/remotes/origin/{default_branch}"),
+            true,
+            "Update default branch of remote",
+        )
+        .context("Could not record the default branch of the remote repository")?;
+    }
 
-    repo.find_remote("origin")?
-        .fetch(&["main"], Some(&mut create_fetch_options()), None)?;
     Ok(())
 }
 
-/// Do the merging part of what `git pull` would do for the main branch
-pub(crate) fn do_git_pull(repo: &Repository) -> anyhow::Result<()> {
-    do_git_fetch(repo)?;
-    log::debug!("Merging remote branch into local copy");
+/// Check out exactly the given *commit* as detached `HEAD`
+///
+/// This behaves like `git reset --hard` followed by `git clean` so that the working directory matches the commit
+/// exactly, regardless of local modifications or previously checked out commits which are not related to it.
+/// If *sparse_paths* are given, only files below these paths are written to (and cleaned in) the working
+/// directory, so nothing is checked out if there are none.
+pub(crate) fn do_git_checkout(
+    repo: &Repository,
+    commit: Oid,
+    sparse_paths: Option<&[String]>,
+    options: &GitOptions,
+) -> anyhow::Result<()> {
+    if options.use_git_executable {
+        return git_cli::do_git_checkout(repo_dir(repo), commit, sparse_paths);
+    }
+    log::debug!("Checking out commit {}", commit);
 
-    // get references to important git objects and analyze merge possibilities
-    let fetch_head = repo.find_reference("FETCH_HEAD")?;
-    let fetch_commit = repo.reference_to_annotated_commit(&fetch_head)?;
-    let analysis = repo.merge_analysis(&[&fetch_commit])?;
-    let mut main_branch = repo
-        .find_branch("main", BranchType::Local)
-        .unwrap()
-        .into_reference();
+    let commit = repo.find_commit(commit)?;
+    // libgit2 treats a checkout without any paths as a checkout of everything
+    if sparse_paths != Some(&[]) {
+        let mut checkout_builder = CheckoutBuilder::default();
+        // sparse paths are names of files and directories rather than patterns
+        checkout_builder
+            .force()
+            .remove_untracked(true)
+            .disable_pathspec_match(true);
+        for path in sparse_paths.unwrap_or_default() {
+            checkout_builder.path(path);
+        }
+        repo.checkout_tree(commit.as_object(), Some(&mut checkout_builder))?;
+    }
+    // libgit2 leaves everything outside of the given paths untouched, including files of earlier checkouts
+    if let Some(sparse_paths) = sparse_paths {
+        let workdir = repo_dir(repo);
+        remove_outside_of(workdir, workdir, sparse_paths)?;
+    }
+    repo.set_head_detached(commit.id())?;
+    Ok(())
+}
 
-    // act according to the analysis
-    if analysis.0.is_up_to_date() {
-        Ok(())
-    } else if analysis.0.is_fast_forward() {
-        main_branch.set_target(fetch_commit.id(), "Fast-Forward")?;
-        repo.set_head(main_branch.name().unwrap())?;
-        repo.checkout_head(Some(CheckoutBuilder::default().force()))?;
+/// Remove everything below *dir* which does not lie inside one of *sparse_paths*
+///
+/// *sparse_paths* are relative to the working directory *workdir* whose `.git` directory is always kept.
+fn remove_outside_of(workdir: &Path, dir: &Path, sparse_paths: &[String]) -> anyhow::Result<()> {
+    for dir_entry in fs::read_dir(dir).context(format!("Could not read {}", dir.display()))? {
+        let dir_entry = dir_entry?;
+        let path = dir_entry.path();
+        let relative_path = path.strip_prefix(workdir)?;
+        if relative_path == Path::new(".git")
+            || sparse_paths
+                .iter()
+                .any(|sparse_path| relative_path.starts_with(sparse_path))
+        {
+            continue;
+        }
 
-        Ok(())
-    } else {
-        Err(anyhow!(
-            "Could not merge remote branch because only fast-forward merges are supported"
-        ))
+        if !dir_entry.file_type()?.is_dir() {
+            fs::remove_file(&path).context(format!("Could not remove {}", path.display()))?;
+        } else if sparse_paths
+            .iter()
+            .any(|sparse_path| Path::new(sparse_path).starts_with(relative_path))
+        {
+            // directories which contain a sparse path are only cleaned
+            remove_outside_of(workdir, &path, sparse_paths)?;
+        } else {
+            fs::remove_dir_all(&path).context(format!("Could not remove {}", path.display()))?;
+        }
     }
+    Ok(())
+}
+
+/// Whether *repo* looks like a usable clone that was created by [`do_git_clone`]
+pub(crate) fn is_intact_clone(repo: &Repository) -> bool {
+    repo.find_remote("origin").is_ok()
+        && repo
+            .references_glob("refs/remotes/origin/*")
+            .map(|mut references| references.next().is_some())
+            .unwrap_or(false)
 }
 
-/// Do what `git clone <url> <path>` would do
-pub(crate) fn do_git_clone(url: &str, path: &Path) -> anyhow::Result<()> {
+/// Do what `git clone --no-checkout <url> <path>` would do
+///
+/// The clone is performed in a temporary directory which is only moved to *path* once it is complete.
+/// This means that an interrupted clone never leaves a broken repository at *path* and that an existing but
+/// broken repository at *path* is replaced.
+pub(crate) fn do_git_clone(
+    url: &str,
+    path: &Path,
+    options: &GitOptions,
+) -> anyhow::Result<Repository> {
     log::debug!("Cloning repository {} to {}", url, path.display());
 
-    match RepoBuilder::new()
-        .fetch_options(create_fetch_options())
-        .clone(url, path)
-    {
-        Ok(_) => Ok(()),
-        Err(e) => Err(e).context("Could not clone the repository"),
+    let file_name = path
+        .file_name()
+        .context("Clone destination does not have a file name")?
+        .to_string_lossy();
+    let tmp_path = path.with_file_name(format!("{}.tmp-{}", file_name, process::id()));
+    if tmp_path.exists() {
+        fs::remove_dir_all(&tmp_path)?;
+    }
+
+    if let Err(e) = init_and_fetch(url, &tmp_path, options) {
+        if let Err(remove_error) = fs::remove_dir_all(&tmp_path) {
+            log::warn!(
+                "Could not remove incomplete clone at {}: {}",
+                tmp_path.display(),
+                remove_error
+            );
+        }
+        return Err(e).context("Could not clone the repository");
+    }
+
+    // replace whatever was previously present at the destination with the new clone
+    if path.exists() {
+        log::debug!("Removing previous clone at {}", path.display());
+        fs::remove_dir_all(path).context(format!(
+            "Could not remove previous clone at {}",
+            path.display()
+        ))?;
+    }
+    fs::rename(&tmp_path, path).context(format!(
+        "Could not move clone from {} to {}",
+        tmp_path.display(),
+        path.display()
+    ))?;
+    Ok(Repository::open(path)?)
+}
+
+/// Initialize a new repository at *path* with *url* as `origin` and fetch it
+fn init_and_fetch(url: &str, path: &Path, options: &GitOptions) -> anyhow::Result<()> {
+    let repo = Repository::init(path).context("Could not initialize repository")?;
+    repo.remote("origin", url)
+        .context("Could not configure remote of repository")?;
+    do_git_fetch(&repo, url, options)
+}
+
+/// Name of the committer if none is configured in git
+const DEFAULT_COMMITTER_NAME: &str = "kustomize-pass";
+
+/// Email of the committer if none is configured in git
+const DEFAULT_COMMITTER_EMAIL: &str = "kustomize-pass@localhost";
+
+/// Options which influence how changed entries are committed by [`do_git_commit`]
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub(crate) struct CommitOptions {
+    /// Subject of the commit message which is used instead of a generated one
+    pub(crate) message: Option<String>,
+
+    /// Whether the commit is signed even if `commit.gpgSign` is not configured
+    pub(crate) sign: bool,
+
+    /// Key which signs the commit instead of `user.signingKey` or the default key of gpg
+    pub(crate) signing_key: Option<String>,
+}
+
+/// Whether *path* is the file of a pass entry
+fn is_entry_file(path: &Path) -> bool {
+    path.extension() == Some("gpg".as_ref())
+}
+
+/// Whether *path* lies inside the checkout set given by *checked_out_paths*
+///
+/// Everything is checked out if no paths are given.
+fn is_checked_out(path: &Path, checked_out_paths: Option<&[String]>) -> bool {
+    checked_out_paths.is_none_or(|checked_out_paths| {
+        checked_out_paths
+            .iter()
+            .any(|checked_out_path| path.starts_with(checked_out_path))
+    })
+}
+
+/// Find all entry files below *store_dir* which differ between the checked out commit of *repo* and its working
+/// directory
+///
+/// *store_dir* and the returned paths are relative to the working directory.
+/// Entries which are missing from the working directory are only considered removed if they lie inside
+/// *checked_out_paths* because all others were never checked out.
+/// A clone in which nothing was checked out yet has no changed entries.
+pub(crate) fn changed_entries(
+    repo: &Repository,
+    store_dir: &Path,
+    checked_out_paths: Option<&[String]>,
+) -> anyhow::Result<Vec<(Delta, PathBuf)>> {
+    let head_tree = match repo.head() {
+        Err(e) if e.code() == git2::ErrorCode::UnbornBranch => return Ok(Vec::new()),
+        head => head
+            .and_then(|head| head.peel_to_tree())
+            .context("Could not find the checked out commit")?,
+    };
+
+    let mut diff_options = DiffOptions::new();
+    diff_options
+        .include_untracked(true)
+        .recurse_untracked_dirs(true);
+    if store_dir != Path::new("") {
+        diff_options.pathspec(store_dir);
+    }
+    let diff = repo.diff_tree_to_workdir(Some(&head_tree), Some(&mut diff_options))?;
+    Ok(diff
+        .deltas()
+        .filter_map(|delta| {
+            let status = match delta.status() {
+                Delta::Untracked => Delta::Added,
+                status => status,
+            };
+            let path = delta
+                .new_file()
+                .path()
+                .or_else(|| delta.old_file().path())?;
+            let is_changed = path.starts_with(store_dir)
+                && is_entry_file(path)
+                && (status != Delta::Deleted || is_checked_out(path, checked_out_paths));
+            is_changed.then(|| (status, path.to_owned()))
+        })
+        .collect())
+}
+
+/// Commit all entries below *store_dir* that were added, modified or removed in the working directory of *repo*
+///
+/// *store_dir* is relative to the working directory and *checked_out_paths* are the paths which were checked out
+/// (see [`changed_entries`]).
+/// Only entry files are committed so that other local modifications never reach the remote.
+/// The commit message names all changed entries and the commit is signed with gpg if *options* or the
+/// `commit.gpgSign` configuration ask for it.
+/// `HEAD` is not moved so that nothing changes if the commit cannot be pushed.
+/// Returns none if no entry was changed.
+pub(crate) fn do_git_commit(
+    repo: &Repository,
+    store_dir: &Path,
+    checked_out_paths: Option<&[String]>,
+    options: &CommitOptions,
+) -> anyhow::Result<Option<Oid>> {
+    let changes = changed_entries(repo, store_dir, checked_out_paths)?;
+    if changes.is_empty() {
+        return Ok(None);
+    }
+    let parent = repo
+        .head()
+        .and_then(|head| head.peel_to_commit())
+        .context("Could not find the checked out commit")?;
+
+    // the index is built from the checked out commit and only used for writing the new tree
+    let mut index = repo.index()?;
+    index.read_tree(&parent.tree()?)?;
+    for (status, path) in &changes {
+        match status {
+            Delta::Deleted => index.remove_path(path),
+            _ => index.add_path(path),
+        }
+        .context(format!("Could not stage {}", path.display()))?;
+    }
+    let tree = repo.find_tree(index.write_tree()?)?;
+    index.read(true)?;
+
+    let changes = changes
+        .iter()
+        .map(|(status, path)| {
+            let entry = path
+                .strip_prefix(store_dir)
+                .unwrap_or(path)
+                .with_extension("");
+            let action = match status {
+                Delta::Added => "Add",
+                Delta::Deleted => "Remove",
+                _ => "Update",
+            };
+            format!("{} {}", action, entry.display())
+        })
+        .collect::<Vec<_>>();
+    let subject = match (&options.message, changes.as_slice()) {
+        (Some(message), _) => message.clone(),
+        (None, [change]) => change.clone(),
+        (None, changes) => format!("Update {} pass entries", changes.len()),
+    };
+    let message = format!("{}\n\n{}\n", subject, changes.join("\n"));
+
+    let signature = repo
+        .signature()
+        .or_else(|_| Signature::now(DEFAULT_COMMITTER_NAME, DEFAULT_COMMITTER_EMAIL))?;
+    let config = repo.config()?;
+    let sign = options.sign
+        || options.signing_key.is_some()
+        || config.get_bool("commit.gpgSign").unwrap_or(false);
+    let commit = if sign {
+        let signing_key = options
+            .signing_key
+            .clone()
+            .or_else(|| config.get_string("user.signingKey").ok());
+        let content =
+            repo.commit_create_buffer(&signature, &signature, &message, &tree, &[&parent])?;
+        let content = content.as_str().context("Commit is not valid UTF-8")?;
+        let gpg_signature = commit_signatures::sign(content.as_bytes(), signing_key.as_deref())?;
+        repo.commit_signed(content, &gpg_signature, None)?
+    } else {
+        repo.commit(None, &signature, &signature, &message, &tree, &[&parent])?
+    };
+    log::debug!("Created commit {}: {}", commit, subject);
+    Ok(Some(commit))
+}
+
+/// The error which is returned when a push is rejected because *branch* of the remote at *url* contains commits
+/// that are missing locally
+pub(crate) fn non_fast_forward_error(url: &str, branch: &str) -> anyhow::Error {
+    anyhow!(
+        "Branch {} of {} contains commits which are not part of the cached clone, nothing was pushed",
+        branch,
+        url
+    )
+}
+
+/// Whether the *reason* given by git or a remote for rejecting a push means that the pushed commit is not a
+/// descendant of the remote branch
+pub(crate) fn is_non_fast_forward(reason: &str) -> bool {
+    ["non-fast-forward", "fetch first"]
+        .iter()
+        .any(|marker| reason.contains(marker))
+}
+
+/// Push *commit* to *branch* of the remote at *url* without overwriting commits of others
+///
+/// If *branch* contains commits which are not ancestors of *commit*, the push is aborted with a
+/// [`non_fast_forward_error`].
+/// Afterwards, *commit* is checked out and recorded as the tip of the remote branch in *repo*.
+pub(crate) fn do_git_push(
+    repo: &Repository,
+    url: &str,
+    branch: &str,
+    commit: Oid,
+    options: &GitOptions,
+) -> anyhow::Result<()> {
+    match options.use_git_executable {
+        true => git_cli::do_git_push(repo_dir(repo), url, branch, commit, options)?,
+        false => push_with_libgit2(repo, url, branch, commit, options)?,
+    }
+
+    // the pushed commit is checked out as if it had just been fetched from the remote
+    repo.reference(
+        &format!("refs/remotes/origin/{branch}"),
+        commit,
+        true,
+        "Record pushed commit",
+    )?;
+    repo.set_head_detached(commit)?;
+    let mut index = repo.index()?;
+    index.read_tree(&repo.find_commit(commit)?.tree()?)?;
+    index.write()?;
+    Ok(())
+}
+
+/// Push *commit* to *branch* of the remote at *url* using libgit2
+fn push_with_libgit2(
+    repo: &Repository,
+    url: &str,
+    branch: &str,
+    commit: Oid,
+    options: &GitOptions,
+) -> anyhow::Result<()> {
+    log::debug!("Pushing commit {} to branch {} of {}", commit, branch, url);
+    configure_libgit2_timeouts()?;
+
+    let mut remote = repo.remote_anonymous(url)?;
+    let host_key_error = RefCell::new(None);
+    let rejection = RefCell::new(None);
+    let mut remote_callbacks = create_remote_callbacks(url, options, &host_key_error);
+    remote_callbacks.push_update_reference(|_, status| {
+        if let Some(status) = status {
+            rejection.replace(Some(status.to_owned()));
+        }
+        Ok(())
+    });
+    let mut push_options = PushOptions::new();
+    push_options.remote_callbacks(remote_callbacks);
+    let push_result = remote.push(
+        &[format!("{commit}:refs/heads/{branch}")],
+        Some(&mut push_options),
+    );
+
+    if let Some(e) = host_key_error.take() {
+        return Err(e.into());
+    }
+    match push_result {
+        Err(e) if e.code() == git2::ErrorCode::NotFastForward => {
+            return Err(non_fast_forward_error(url, branch))
+        }
+        result => result.context("Could not push to remote repository")?,
+    }
+    match rejection.take() {
+        Some(status) if is_non_fast_forward(&status) => Err(non_fast_forward_error(url, branch)),
+        Some(status) => {
+            bail!("The remote repository rejected the push to branch {branch}: {status}")
+        }
+        None => Ok(()),
     }
 }
@@ -13,10 +13,23 @@ pub struct FieldRef {
     /// The current value of the field
     ///
     /// Can be any value - string, number, boolean, array or object
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub current_value: Option<Value>,
 
     /// The proposed value of the field to fix an issue
     ///
     /// Can be any value - string, number, boolean, array or object
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub proposed_value: Option<Value>,
 }
+
+impl FieldRef {
+    /// Create a reference to the field at *path* without any values
+    pub fn new(path: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            current_value: None,
+            proposed_value: None,
+        }
+    }
+}
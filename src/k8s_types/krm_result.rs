@@ -1,7 +1,8 @@
-use crate::k8s_types::{FileRef, ResourceRef};
+use crate::k8s_types::{FieldRef, FileRef, ResourceRef};
 use serde::{Deserialize, Serialize};
 use serde_yaml::Mapping;
 
+/// A structured result that is emitted by a KRM function for observability and debugging purposes
 #[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone, Hash)]
 #[serde(rename_all = "camelCase")]
 pub struct KrmResult {
@@ -10,23 +11,74 @@ pub struct KrmResult {
 
     /// The severity of the result
     ///
-    /// One of:
-    /// - `error` (interpreted as error if not given)
-    /// - `warning`
-    /// - `info`
-    pub severity: Option<String>,
+    /// Interpreted as error if not given
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub severity: Option<Severity>,
 
     /// The metadata for referencing a Kubernetes object associated with a result
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub resource_ref: Option<ResourceRef>,
 
     /// The reference to a field in the object
     /// If defined, `resource_ref` must also be provided
-    pub field: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<FieldRef>,
 
     /// The reference to a file containing the resource
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub file: Option<FileRef>,
 
     /// An unstructured key value map stored with a result that may be set by external tools to store and
     /// retrieve arbitrary metadata
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub tags: Option<Mapping>,
 }
+
+impl KrmResult {
+    /// Create a new result with the given severity and message but without any references
+    pub fn new(severity: Severity, message: String) -> Self {
+        Self {
+            message,
+            severity: Some(severity),
+            resource_ref: None,
+            field: None,
+            file: None,
+            tags: None,
+        }
+    }
+}
+
+/// Possible severities of a [`KrmResult`]
+#[derive(Copy, Clone, Debug, Hash, Serialize, Deserialize, Eq, PartialEq)]
+pub enum Severity {
+    /// The function failed
+    #[serde(rename = "error")]
+    Error,
+
+    /// The function succeeded but something might not be as intended
+    #[serde(rename = "warning")]
+    Warning,
+
+    /// Purely informational
+    #[serde(rename = "info")]
+    Info,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_yaml::Value;
+
+    #[test]
+    fn field_is_serialized_as_field_ref() {
+        let mut result = KrmResult::new(Severity::Warning, "message".to_string());
+        result.field = Some(FieldRef::new("data.example-key"));
+
+        let serialized = serde_yaml::to_value(&result).unwrap();
+        let expected: Value = serde_yaml::from_str(
+            "message: message\nseverity: warning\nfield:\n  path: data.example-key\n",
+        )
+        .unwrap();
+        assert_eq!(serialized, expected);
+    }
+}
@@ -8,6 +8,7 @@ mod krm_result;
 mod object_meta;
 mod pass_secret;
 mod pass_source;
+mod pass_transformer;
 mod resource_list;
 mod resource_ref;
 mod type_id;
@@ -16,9 +17,11 @@ pub use field_ref::FieldRef;
 pub use file_ref::FileRef;
 pub use generator_behavior::GeneratorBehavior;
 pub use k8s_secret::V1Secret;
+pub use krm_result::{KrmResult, Severity};
 pub use object_meta::K8sObjectMeta;
 pub use pass_secret::V1Beta1PassSecret;
 pub use pass_source::PassSource;
+pub use pass_transformer::V1Beta1PassTransformer;
 pub use resource_list::V1ResourceList;
 pub use resource_ref::ResourceRef;
 pub use type_id::K8sTypeId;
@@ -30,6 +30,15 @@ pub struct V1Beta1PassSecret {
     /// Keys will be copied to the resulting kubernetes secret object while values will be retrieved from pass.
     /// This works by using the value of **this** object as the name of the entry in pass.
     ///
+    /// Only a part of the entry can be used by appending a selector to the name (e.g. `web/github#login`):
+    /// - `#password` selects the first line
+    /// - `#otp` generates the current one-time password from an `otpauth://totp/...` URI in the entry.
+    ///   Entries which consist only of such a URI produce the one-time password even without this selector.
+    /// - `#line:<n>` selects the n-th line (starting at 1)
+    /// - `#<key>` selects the value of a `key: value` line (excluding the first line, matched case-insensitively)
+    /// - `#$.<path>` selects a value from a JSON or YAML entry (e.g. `gcp/sa.json#$.private_key`).
+    ///   For entries whose names end with `.json`, `.yaml` or `.yml`, the `$.` prefix can be omitted.
+    ///
     /// Each key must consist of alphanumeric characters, '-', '_' or '.'.
     pub data: BTreeMap<String, String>,
 
@@ -1,12 +1,19 @@
-use crate::git_util;
-use anyhow::Context;
+use crate::cache_metadata::{cache_dir, CacheMetadata};
+use crate::git_util::{CommitOptions, GitOptions, GitRevision};
+use crate::known_hosts::HostKeyError;
+use crate::lockfile::{self, Lockfile};
+use crate::password_store::PasswordStore;
+use crate::results::ResultCollector;
+use crate::{archive, clone_lock, commit_signatures, env_config, git_util, url_rewrite};
+use anyhow::{bail, Context};
 use blake2::digest::{Update, VariableOutput};
 use blake2::Blake2bVar;
-use directories::ProjectDirs;
-use git2::Repository;
+use directories::UserDirs;
+use git2::{Oid, Repository};
 use okapi::schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
 
 /// A reference to the source from which [`PassSecret`](crate::k8s_type::PassSecret) data is retrieved
 #[derive(Serialize, Deserialize, Eq, PartialEq, Debug, JsonSchema)]
@@ -15,18 +22,63 @@ pub enum PassSource {
     /// Use the existing store located at `~/.password-store` or pointed to by environment variable `PASSWORD_STORE_DIR`
     Local,
 
-    /// Use a git repository which contains a password store at its root
-    Git(GitPassSource),
+    /// Use the existing store in a specific directory
+    Path(PathPassSource),
+
+    /// Use a git repository which contains a password store at its root or in a subdirectory
+    Git(Box<GitPassSource>),
+
+    /// Use a `git bundle` file which contains a password store at its root or in a subdirectory
+    Bundle(BundlePassSource),
+
+    /// Use a `.tar.gz` archive which contains a password store at its root or in a subdirectory
+    Archive(ArchivePassSource),
 }
 
 impl PassSource {
-    /// Setup the local directory so that it is as intended
-    pub(crate) fn setup(&self) -> anyhow::Result<PathBuf> {
+    /// Setup the local directory so that it is as intended and return a handle to the store in it
+    ///
+    /// *referenced_entries* are the names of all entries which will be retrieved from the store.
+    /// Sources which support it may choose to only provide these entries.
+    /// Warnings which do not prevent the store from being used are reported to *results*.
+    pub(crate) fn setup(
+        &self,
+        referenced_entries: &[&str],
+        results: &mut ResultCollector,
+    ) -> anyhow::Result<PasswordStore> {
         log::debug!("Setting up password store source '{:?}'", self);
 
+        let store_dir = match self {
+            PassSource::Local => libpass::password_store_dir()?,
+            PassSource::Path(path_source) => path_source.setup()?,
+            PassSource::Git(git_source) => git_source.setup(referenced_entries, results)?,
+            PassSource::Bundle(bundle_source) => bundle_source.setup()?,
+            PassSource::Archive(archive_source) => archive_source.setup()?,
+        };
+        Ok(PasswordStore::new(store_dir))
+    }
+
+    /// Record the commit that this source currently resolves to in *lockfile* if it is a git source
+    pub(crate) fn update_lock(&self, lockfile: &mut Lockfile) -> anyhow::Result<()> {
         match self {
-            PassSource::Local => Ok(libpass::password_store_dir()?),
-            PassSource::Git(git_source) => git_source.setup(),
+            // bundles and archives are identified by their content and are therefore always reproducible
+            PassSource::Local
+            | PassSource::Path(_)
+            | PassSource::Bundle(_)
+            | PassSource::Archive(_) => Ok(()),
+            PassSource::Git(git_source) => git_source.update_lock(lockfile),
+        }
+    }
+
+    /// Commit the entries which were changed in the cached clone of a git source and push them to its remote
+    pub(crate) fn push_changes(&self, commit_options: &CommitOptions) -> anyhow::Result<()> {
+        match self {
+            // all other sources are either managed by pass itself or read-only
+            PassSource::Local
+            | PassSource::Path(_)
+            | PassSource::Bundle(_)
+            | PassSource::Archive(_) => Ok(()),
+            PassSource::Git(git_source) => git_source.push_changes(commit_options),
         }
     }
 }
@@ -38,12 +90,482 @@ impl Default for PassSource {
 }
 
 #[derive(Serialize, Deserialize, Eq, PartialEq, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
 pub struct GitPassSource {
     /// Git clone url
+    ///
+    /// The url is rewritten according to the `url.<base>.insteadOf` rules of the git configuration and the url
+    /// overrides of kustomize-pass before it is used.
     pub url: String,
+
+    /// Urls of mirrors of the repository which are tried in order if it cannot be fetched from `url`
+    ///
+    /// All mirrors share the cache of `url` so they must contain the same history.
+    /// Mirror urls are rewritten in the same way as `url`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mirrors: Vec<String>,
+
+    /// A branch, tag or any other revision that should be checked out
+    ///
+    /// At most one of `ref`, `branch`, `tag` and `commit` may be given.
+    /// If none is given, the default branch of the remote repository (its `HEAD`) is used.
+    #[serde(rename = "ref")]
+    pub git_ref: Option<String>,
+
+    /// The branch that should be checked out
+    pub branch: Option<String>,
+
+    /// The tag that should be checked out
+    pub tag: Option<String>,
+
+    /// The (possibly abbreviated) hash of the commit that should be checked out
+    pub commit: Option<String>,
+
+    /// Path of the directory inside the repository which contains the password store
+    ///
+    /// If given, only this directory is checked out.
+    /// Defaults to the repository root.
+    pub subdir: Option<String>,
+
+    /// Path of an ssh private key which is used to authenticate against ssh remotes
+    ///
+    /// A leading `~/` refers to the home directory.
+    /// The key is tried before the ssh agent and the default keys `~/.ssh/id_ed25519`, `~/.ssh/id_ecdsa` and
+    /// `~/.ssh/id_rsa`.
+    /// Defaults to the value of the `KUSTOMIZE_PASS_SSH_KEY` environment variable.
+    /// Passphrases of protected keys are retrieved from the program given by `GIT_ASKPASS` or `SSH_ASKPASS`.
+    pub ssh_key: Option<String>,
+
+    /// Name of an environment variable which contains a token for authenticating against https remotes
+    ///
+    /// The token is used as password together with the user name of the url or `x-access-token` if the url does
+    /// not contain one.
+    /// Without a token, credentials are taken from `~/.netrc`, the git credential helpers or the program given by
+    /// `GIT_ASKPASS`.
+    pub token_env: Option<String>,
+
+    /// Additional trusted ssh host keys in the format of a `known_hosts` file
+    ///
+    /// The host key of ssh remotes is always verified against `~/.ssh/known_hosts` and `/etc/ssh/ssh_known_hosts`.
+    /// Entries given here are trusted in addition to these files.
+    pub known_hosts: Option<String>,
+
+    /// Require commits to be signed by trusted OpenPGP keys before they are checked out
+    pub require_signed_commits: Option<SignedCommitsPolicy>,
+
+    /// How the repository is accessed
+    ///
+    /// Defaults to the value of the `KUSTOMIZE_PASS_GIT_BACKEND` environment variable or `libgit2`.
+    pub backend: Option<GitBackend>,
+
+    /// Number of commits of each branch and tag that are fetched instead of the complete history
+    ///
+    /// If a requested commit is not part of the shallow history, the complete history is fetched once.
+    pub depth: Option<u32>,
+
+    /// Only check out the entries which are referenced by the resource using this source
+    ///
+    /// This reduces the time and disk space needed for repositories which contain many entries.
+    #[serde(default)]
+    pub sparse: bool,
+}
+
+/// A password store in a local directory
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PathPassSource {
+    /// Path of the password store directory
+    ///
+    /// Relative paths are resolved against the directory of the kustomization so that a store can be committed
+    /// together with the kustomization.
+    pub path: String,
+}
+
+/// A password store which is shipped as `git bundle` file
+///
+/// The bundle is cloned into a local cache that is identified by the content of the bundle so the network is never
+/// accessed.
+/// Bundles are read by the `git` executable of the system.
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct BundlePassSource {
+    /// Path of the bundle file
+    ///
+    /// Relative paths are resolved against the directory of the kustomization.
+    pub bundle: String,
+
+    /// A branch, tag or any other revision of the bundle that should be checked out
+    ///
+    /// Defaults to the revision which the `HEAD` of the bundle points to.
+    #[serde(rename = "ref")]
+    pub git_ref: Option<String>,
+
+    /// Path of the directory inside the bundled repository which contains the password store
+    ///
+    /// Defaults to the repository root.
+    pub subdir: Option<String>,
+}
+
+/// A password store which is shipped as gzip compressed tar archive
+///
+/// The archive is unpacked into a local cache that is identified by the content of the archive.
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ArchivePassSource {
+    /// Path of the `.tar.gz` file
+    ///
+    /// Relative paths are resolved against the directory of the kustomization.
+    pub archive: String,
+
+    /// Path of the directory inside the archive which contains the password store
+    ///
+    /// Defaults to the root of the archive.
+    pub subdir: Option<String>,
+}
+
+/// Implementation which is used to clone, fetch and check out git sources
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Copy, Clone, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum GitBackend {
+    /// The builtin implementation based on libgit2 which mimics the behavior of git
+    Libgit2,
+
+    /// The `git` executable of the system
+    ///
+    /// This respects the complete configuration of git and ssh (e.g. `~/.ssh/config` or credential helpers) so that
+    /// every repository which can be cloned with `git clone` can also be used as source.
+    Git,
+}
+
+/// Requirements on the OpenPGP signatures of commits of a git source
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SignedCommitsPolicy {
+    /// Fingerprints of the keys whose signatures are trusted
+    ///
+    /// Either the fingerprint of the signing subkey or of its primary key may be given.
+    /// The keys need to be present in the gpg keyring of the user running kustomize.
+    pub trusted_fingerprints: Vec<String>,
+
+    /// Whether every commit since the last verified one must be signed as well
+    ///
+    /// If disabled (the default), only the checked-out commit must be signed.
+    /// The first time a repository is verified, only the checked-out commit is verified.
+    #[serde(default)]
+    pub verify_all_commits: bool,
+}
+
+/// Validate a `subdir` field of a source and return it as relative path inside that source
+fn relative_subdir(subdir: Option<&str>) -> anyhow::Result<Option<&Path>> {
+    match subdir {
+        None => Ok(None),
+        Some(subdir) => {
+            let subdir = Path::new(subdir.trim_matches('/'));
+            if subdir
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)))
+            {
+                Ok(Some(subdir).filter(|subdir| !subdir.as_os_str().is_empty()))
+            } else {
+                bail!(
+                    "subdir {} must be a relative path that stays inside the source",
+                    subdir.display()
+                )
+            }
+        }
+    }
+}
+
+/// Read the file at *path* (relative to the kustomization directory) and return its absolute path and content
+fn read_source_file(path: &str, description: &str) -> anyhow::Result<(PathBuf, Vec<u8>)> {
+    let path = fs::canonicalize(path).context(format!("Could not find {description} {path}"))?;
+    let content =
+        fs::read(&path).context(format!("Could not read {} {}", description, path.display()))?;
+    Ok((path, content))
+}
+
+/// Construct a cache path for a source of the given *kind* that is unique to *content*
+fn content_cache_path(kind: &str, content: &[u8]) -> anyhow::Result<PathBuf> {
+    const SUFFIX_LEN: usize = 12;
+
+    let mut hasher = Blake2bVar::new(SUFFIX_LEN)
+        .context("Could not construct digest algorithm for hashing the source content")?;
+    hasher.update(content);
+    let mut digest = [0u8; SUFFIX_LEN];
+    hasher
+        .finalize_variable(&mut digest)
+        .context("Could not finalize digest of the source content")?;
+
+    Ok(cache_dir()?.join(format!("{}-{}", kind, hex::encode(digest))))
+}
+
+/// Locate the password store in the optional *subdir* of the source which is cached at *path*
+fn store_dir(path: PathBuf, subdir: Option<&Path>, source: &str) -> anyhow::Result<PathBuf> {
+    let store_dir = match subdir {
+        None => path,
+        Some(subdir) => path.join(subdir),
+    };
+    if !store_dir.is_dir() {
+        bail!(
+            "{} does not contain the directory {}",
+            source,
+            subdir.unwrap_or(Path::new("")).display()
+        );
+    }
+    Ok(store_dir)
+}
+
+impl PathPassSource {
+    fn setup(&self) -> anyhow::Result<PathBuf> {
+        let store_dir = fs::canonicalize(&self.path)
+            .context(format!("Could not find password store {}", self.path))?;
+        if !store_dir.is_dir() {
+            bail!("Password store {} is not a directory", store_dir.display());
+        }
+        Ok(store_dir)
+    }
+}
+
+impl BundlePassSource {
+    fn setup(&self) -> anyhow::Result<PathBuf> {
+        let revision = match &self.git_ref {
+            None => GitRevision::DefaultBranch,
+            Some(git_ref) => GitRevision::Ref(git_ref),
+        };
+        let subdir = relative_subdir(self.subdir.as_deref())?;
+        let sparse_paths = subdir.map(|subdir| vec![subdir.to_string_lossy().into_owned()]);
+
+        let (bundle_path, content) = read_source_file(&self.bundle, "bundle")?;
+        let path = content_cache_path("bundle", &content)?;
+        clone_lock::lock_clone(&path)?;
+
+        // libgit2 cannot read bundles
+        let git_options = GitOptions {
+            use_git_executable: true,
+            ..GitOptions::default()
+        };
+        let cached = Repository::open(&path)
+            .ok()
+            .filter(git_util::is_intact_clone);
+        let repo = match cached {
+            Some(repo) => repo,
+            None => git_util::do_git_clone(&bundle_path.to_string_lossy(), &path, &git_options)
+                .context(format!("Could not clone bundle {}", bundle_path.display()))?,
+        };
+
+        let commit = git_util::resolve_revision(&repo, revision)
+            .context(format!("Could not resolve {revision}"))?;
+        git_util::do_git_checkout(&repo, commit, sparse_paths.as_deref(), &git_options)?;
+
+        let mut metadata = CacheMetadata::load(&path);
+        metadata.record_use(&bundle_path.to_string_lossy());
+        metadata.store(&path)?;
+        store_dir(path, subdir, &format!("Bundle {}", self.bundle))
+    }
+}
+
+impl ArchivePassSource {
+    fn setup(&self) -> anyhow::Result<PathBuf> {
+        let subdir = relative_subdir(self.subdir.as_deref())?;
+
+        let (archive_path, content) = read_source_file(&self.archive, "archive")?;
+        let path = content_cache_path("archive", &content)?;
+        clone_lock::lock_clone(&path)?;
+
+        // archives are unpacked atomically so an existing directory is always complete
+        if !path.is_dir() {
+            archive::unpack_tar_gz(&content, &path).context(format!(
+                "Could not unpack archive {}",
+                archive_path.display()
+            ))?;
+        }
+
+        let mut metadata = CacheMetadata::load(&path);
+        metadata.record_use(&archive_path.to_string_lossy());
+        metadata.store(&path)?;
+        store_dir(path, subdir, &format!("Archive {}", self.archive))
+    }
 }
 
 impl GitPassSource {
+    /// The revision that should be checked out as configured by the `ref`, `branch`, `tag` and `commit` fields
+    fn revision(&self) -> anyhow::Result<GitRevision<'_>> {
+        match (&self.git_ref, &self.branch, &self.tag, &self.commit) {
+            (None, None, None, None) => Ok(GitRevision::DefaultBranch),
+            (Some(git_ref), None, None, None) => Ok(GitRevision::Ref(git_ref)),
+            (None, Some(branch), None, None) => Ok(GitRevision::Branch(branch)),
+            (None, None, Some(tag), None) => Ok(GitRevision::Tag(tag)),
+            (None, None, None, Some(commit)) => Ok(GitRevision::Commit(commit)),
+            _ => bail!("Only one of ref, branch, tag and commit may be specified for a git source"),
+        }
+    }
+
+    /// The validated `subdir` field as relative path inside the repository
+    fn subdir(&self) -> anyhow::Result<Option<&Path>> {
+        relative_subdir(self.subdir.as_deref())
+    }
+
+    /// Options for accessing the remote repository as configured by this source and the environment
+    fn git_options(&self) -> anyhow::Result<GitOptions> {
+        let ssh_key = match self.ssh_key.clone().or_else(env_config::ssh_key) {
+            None => None,
+            Some(ssh_key) => match ssh_key.strip_prefix("~/") {
+                None => Some(PathBuf::from(ssh_key)),
+                Some(relative_path) => Some(
+                    UserDirs::new()
+                        .context("Could not determine home directory to locate the ssh key")?
+                        .home_dir()
+                        .join(relative_path),
+                ),
+            },
+        };
+
+        let backend = match (self.backend, env_config::git_backend()) {
+            (Some(backend), _) => backend,
+            (None, None) => GitBackend::Libgit2,
+            (None, Some(backend)) => serde_yaml::from_str(&backend).context(format!(
+                "Environment variable {} contains the unknown git backend {}",
+                env_config::GIT_BACKEND_ENV,
+                backend
+            ))?,
+        };
+
+        if self.depth == Some(0) {
+            bail!("depth must be at least 1");
+        }
+        if let Some(token_env) = &self.token_env {
+            if token_env.is_empty()
+                || token_env.starts_with(|c: char| c.is_ascii_digit())
+                || !token_env
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_')
+            {
+                bail!(
+                    "tokenEnv {} is not a valid environment variable name",
+                    token_env
+                );
+            }
+        }
+
+        Ok(GitOptions {
+            use_git_executable: backend == GitBackend::Git,
+            ssh_key,
+            known_hosts: self.known_hosts.clone(),
+            depth: self.depth,
+            token_env: self.token_env.clone(),
+            transfer_timeout: env_config::transfer_timeout()?,
+            retries: env_config::fetch_retries()?,
+        })
+    }
+
+    /// Verify the signatures of *commit* and possibly its predecessors if required by this source
+    ///
+    /// If the history was rewritten since the last verified commit, e.g. by a force-push, the commits in between
+    /// cannot be determined, so only *commit* is verified and a warning is reported.
+    fn verify_signatures(
+        &self,
+        repo: &Repository,
+        commit: Oid,
+        metadata: &mut CacheMetadata,
+        results: &mut ResultCollector,
+    ) -> anyhow::Result<()> {
+        let policy = match &self.require_signed_commits {
+            None => return Ok(()),
+            Some(policy) => policy,
+        };
+        if policy.trusted_fingerprints.is_empty() {
+            bail!("requireSignedCommits must contain at least one trusted fingerprint");
+        }
+
+        let last_verified = metadata
+            .last_verified_commit
+            .as_deref()
+            .filter(|_| policy.verify_all_commits)
+            .and_then(|last_verified| Oid::from_str(last_verified).ok());
+        // the last verified commit might be gone or unrelated if the remote history was rewritten
+        let is_related = |last_verified: Oid| {
+            last_verified == commit
+                || repo
+                    .graph_descendant_of(commit, last_verified)
+                    .unwrap_or(false)
+                || repo
+                    .graph_descendant_of(last_verified, commit)
+                    .unwrap_or(false)
+        };
+        let last_verified = match last_verified {
+            Some(last_verified) if !is_related(last_verified) => {
+                results.report_warning(
+                    "source",
+                    format!(
+                        "The history of {} was rewritten since commit {} was verified, only the signature of commit {} is verified",
+                        self.url, last_verified, commit
+                    ),
+                );
+                None
+            }
+            last_verified => last_verified,
+        };
+        commit_signatures::verify_commits(
+            repo,
+            commit,
+            last_verified,
+            &policy.trusted_fingerprints,
+        )?;
+
+        metadata.last_verified_commit = Some(commit.to_string());
+        Ok(())
+    }
+
+    /// The url from which the repository is actually fetched after all url rewrites are applied
+    fn effective_url(&self) -> anyhow::Result<String> {
+        url_rewrite::rewrite_url(&self.url)
+    }
+
+    /// Run *action* with the overridden url of the repository and then of each mirror until it succeeds
+    ///
+    /// The url that was used successfully is returned together with the result of *action*.
+    /// If the data is served by a mirror, this is reported as warning together with the errors of the urls that
+    /// were tried before.
+    fn with_fallback_urls<T>(
+        &self,
+        description: &str,
+        results: &mut ResultCollector,
+        mut action: impl FnMut(&str) -> anyhow::Result<T>,
+    ) -> anyhow::Result<(String, T)> {
+        let urls = std::iter::once(&self.url)
+            .chain(self.mirrors.iter())
+            .map(|url| url_rewrite::apply_overrides(url))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let mut errors = Vec::new();
+        for url in urls {
+            match action(&url) {
+                Ok(result) => {
+                    if !errors.is_empty() {
+                        results.report_warning(
+                            "source",
+                            format!(
+                                "Could not {} {}, using mirror {} instead: {}",
+                                description,
+                                self.url,
+                                url,
+                                errors.join("; ")
+                            ),
+                        );
+                    }
+                    return Ok((url, result));
+                }
+                // an unverifiable host key could mean that someone impersonates the remote which must not go unnoticed
+                Err(e) if e.downcast_ref::<HostKeyError>().is_some() => return Err(e),
+                Err(e) => {
+                    log::debug!("Could not {} {}: {:#}", description, url, e);
+                    errors.push(format!("{url}: {e:#}"));
+                }
+            }
+        }
+        bail!("{}", errors.join("; "))
+    }
+
     // construct a path to which this repository can be cloned that is unique to it
     fn unique_path(&self) -> anyhow::Result<PathBuf> {
         const SUFFIX_LEN: usize = 12;
@@ -52,7 +574,14 @@ impl GitPassSource {
         let mut hasher = Blake2bVar::new(SUFFIX_LEN).context(
             "Could not construct digest algorithm for constructing a unique password source path",
         )?;
-        hasher.update(self.url.as_bytes());
+        hasher.update(self.effective_url()?.as_bytes());
+        match self.revision()? {
+            GitRevision::DefaultBranch => {}
+            revision => hasher.update(revision.to_string().as_bytes()),
+        }
+        if let Some(subdir) = self.subdir()? {
+            hasher.update(subdir.to_string_lossy().as_bytes());
+        }
         let mut digest = [0u8; SUFFIX_LEN];
         hasher
             .finalize_variable(&mut digest)
@@ -60,10 +589,7 @@ impl GitPassSource {
 
         // append hex-encoded digest to '~/.password-store-'
         let unique_path = "password-store-".to_owned() + &hex::encode(digest);
-        let unique_path = ProjectDirs::from("de", "ftsell", "kustomize-pass")
-            .context("Could not retrieve project directories")?
-            .data_local_dir()
-            .join(unique_path);
+        let unique_path = cache_dir()?.join(unique_path);
 
         log::trace!(
             "Computed unique path for repo {} is {}",
@@ -73,13 +599,404 @@ impl GitPassSource {
         Ok(unique_path)
     }
 
-    fn setup(&self) -> anyhow::Result<PathBuf> {
+    /// Open the cached clone at *path* if it exists and is intact
+    fn open_cached(&self, path: &Path) -> Option<Repository> {
+        if !path.exists() {
+            return None;
+        }
+
+        match Repository::open(path) {
+            Ok(repo) if git_util::is_intact_clone(&repo) => Some(repo),
+            Ok(_) | Err(_) => {
+                log::warn!(
+                    "Cached clone of {} at {} is broken and will be replaced",
+                    self.url,
+                    path.display()
+                );
+                None
+            }
+        }
+    }
+
+    /// Clone the repository to *path*, replacing anything that is already present there
+    fn clone_to(
+        &self,
+        path: &Path,
+        metadata: &mut CacheMetadata,
+        results: &mut ResultCollector,
+    ) -> anyhow::Result<Repository> {
+        if env_config::offline() {
+            bail!(
+                "Repository {} is not cached yet and cannot be cloned because {} is set",
+                self.url,
+                env_config::OFFLINE_ENV
+            );
+        }
+
+        let git_options = self.git_options()?;
+        let (url, repo) = self.with_fallback_urls("clone", results, |url| {
+            git_util::do_git_clone(url, path, &git_options)
+        })?;
+        metadata.record_fetch(&url);
+        Ok(repo)
+    }
+
+    /// Fetch the already cloned *repo* unless that is not necessary or not possible
+    ///
+    /// A fetch is not necessary if the last one happened within the configured fetch TTL (unless *ignore_ttl* is
+    /// set) or if a commit is requested that is already present.
+    /// If fetching fails, the previously cached content is used and a warning is reported.
+    fn fetch_if_necessary(
+        &self,
+        repo: &Repository,
+        revision: GitRevision,
+        metadata: &mut CacheMetadata,
+        ignore_ttl: bool,
+        results: &mut ResultCollector,
+    ) -> anyhow::Result<()> {
+        if env_config::offline() {
+            log::debug!(
+                "Not fetching {} because {} is set",
+                self.url,
+                env_config::OFFLINE_ENV
+            );
+            return Ok(());
+        }
+        if matches!(revision, GitRevision::Commit(_))
+            && git_util::resolve_revision(repo, revision).is_ok()
+        {
+            log::debug!(
+                "Not fetching {} because {} is already present",
+                self.url,
+                revision
+            );
+            return Ok(());
+        }
+        if !ignore_ttl && metadata.is_fresh(env_config::fetch_ttl()?) {
+            log::debug!(
+                "Not fetching {} because the last fetch is recent enough",
+                self.url
+            );
+            return Ok(());
+        }
+
+        // a clone whose complete history was fetched once must not become shallow again
+        let mut git_options = self.git_options()?;
+        if !repo.is_shallow() {
+            git_options.depth = None;
+        }
+        match self.with_fallback_urls("fetch", results, |url| {
+            git_util::do_git_fetch(repo, url, &git_options)
+        }) {
+            Ok((url, _)) => metadata.record_fetch(&url),
+            Err(e) if e.downcast_ref::<HostKeyError>().is_some() => return Err(e),
+            Err(e) => results.report_warning(
+                "source",
+                format!(
+                    "Could not fetch {}, using the previously cached content from {} instead: {:#}",
+                    self.url,
+                    metadata.fetched_from.as_deref().unwrap_or("an unknown url"),
+                    e
+                ),
+            ),
+        }
+        Ok(())
+    }
+
+    /// Paths inside the repository which are checked out for *referenced_entries* or none if everything is
+    ///
+    /// Without sparse checkout, this is only the configured subdirectory (if any).
+    /// With sparse checkout, nothing at all is checked out if no entries are referenced.
+    fn sparse_paths(&self, referenced_entries: &[&str]) -> anyhow::Result<Option<Vec<String>>> {
+        let subdir = self.subdir()?;
+        if !self.sparse {
+            return Ok(subdir.map(|subdir| vec![subdir.to_string_lossy().into_owned()]));
+        }
+
+        let subdir = subdir.unwrap_or(Path::new(""));
+        Ok(Some(
+            referenced_entries
+                .iter()
+                .map(|entry| {
+                    subdir
+                        .join(entry.trim_start_matches('/').to_string() + ".gpg")
+                        .to_string_lossy()
+                        .into_owned()
+                })
+                .collect(),
+        ))
+    }
+
+    fn setup(
+        &self,
+        referenced_entries: &[&str],
+        results: &mut ResultCollector,
+    ) -> anyhow::Result<PathBuf> {
+        let sparse_paths = self.sparse_paths(referenced_entries)?;
+
+        // a commit which is recorded in the lockfile takes precedence over the configured revision
+        let revision = self.revision()?;
+        let lockfile = Lockfile::load_current()?;
+        match lockfile
+            .as_ref()
+            .and_then(|lockfile| lockfile.locked_commit(&self.url, revision))
+        {
+            Some(commit) => {
+                log::debug!("Using commit {} of {} from lockfile", commit, self.url);
+                Ok(self
+                    .setup_revision(
+                        GitRevision::Commit(commit),
+                        false,
+                        sparse_paths.as_deref(),
+                        results,
+                    )?
+                    .0)
+            }
+            None => {
+                if lockfile.is_some() {
+                    results.report_warning(
+                        "source",
+                        format!(
+                            "The {} of {} is not recorded in {} and is therefore not reproducible",
+                            revision,
+                            self.url,
+                            lockfile::LOCKFILE_NAME
+                        ),
+                    );
+                }
+                Ok(self
+                    .setup_revision(revision, false, sparse_paths.as_deref(), results)?
+                    .0)
+            }
+        }
+    }
+
+    /// Resolve *revision* in *repo* while fetching the complete history if it is not part of a shallow clone
+    fn resolve_revision(
+        &self,
+        repo: &Repository,
+        revision: GitRevision,
+        results: &mut ResultCollector,
+    ) -> anyhow::Result<Oid> {
+        match git_util::resolve_revision(repo, revision) {
+            Err(_) if repo.is_shallow() && !env_config::offline() => {
+                log::info!(
+                    "The {} of {} is not part of the shallow clone, fetching the complete history",
+                    revision,
+                    self.url
+                );
+                let git_options = GitOptions {
+                    depth: Some(git_util::UNSHALLOW_DEPTH),
+                    ..self.git_options()?
+                };
+                self.with_fallback_urls("fetch", results, |url| {
+                    git_util::do_git_fetch(repo, url, &git_options)
+                })?;
+                git_util::resolve_revision(repo, revision)
+            }
+            result => result,
+        }
+        .context(format!("Could not resolve {revision}"))
+    }
+
+    /// Resolve the configured revision again and record the resulting commit in *lockfile*
+    ///
+    /// The repository is always fetched, regardless of the fetch TTL, so that the newest commit is recorded.
+    /// Nothing is checked out because only the commit is needed.
+    /// Warnings are only logged because there is no output in which they could be reported.
+    pub(crate) fn update_lock(&self, lockfile: &mut Lockfile) -> anyhow::Result<()> {
+        let revision = self.revision()?;
+        let (path, _, metadata, commit) =
+            self.update_cache(revision, true, &mut ResultCollector::default())?;
+        metadata.store(&path)?;
+        log::info!("Locking {} of {} to commit {}", revision, self.url, commit);
+        lockfile.lock(&self.url, revision, commit.to_string());
+        Ok(())
+    }
+
+    /// The branch of the remote to which changes are pushed
+    fn push_branch(&self, repo: &Repository) -> anyhow::Result<String> {
+        match self.revision()? {
+            GitRevision::Branch(branch) => Ok(branch.to_string()),
+            GitRevision::Ref(name)
+                if repo
+                    .find_reference(&format!("refs/remotes/origin/{name}"))
+                    .is_ok() =>
+            {
+                Ok(name.to_string())
+            }
+            GitRevision::DefaultBranch => repo
+                .find_reference(git_util::REMOTE_HEAD)
+                .ok()
+                .and_then(|remote_head| {
+                    remote_head
+                        .symbolic_target()?
+                        .strip_prefix("refs/remotes/origin/")
+                        .map(str::to_string)
+                })
+                .context("Could not determine the default branch of the remote repository"),
+            revision => bail!(
+                "Cannot push to the {} of {} because it is not a branch",
+                revision,
+                self.url
+            ),
+        }
+    }
+
+    /// Commit the entries which were changed in the cached clone and push them to the configured branch
+    ///
+    /// Changes are only pushed to `url` (after `pushInsteadOf` rules are applied) because mirrors are expected to
+    /// follow it.
+    /// Commits are always signed if this source requires signed commits.
+    /// If the push fails, the changed entries are left uncommitted in the cached clone.
+    pub(crate) fn push_changes(&self, commit_options: &CommitOptions) -> anyhow::Result<()> {
+        if env_config::offline() {
+            bail!(
+                "Cannot push to {} because {} is set",
+                self.url,
+                env_config::OFFLINE_ENV
+            );
+        }
+
+        let git_options = self.git_options()?;
+        let path = self.unique_path()?;
+        clone_lock::lock_clone(&path)?;
+        let repo = self.open_cached(&path).context(format!(
+            "There is no cached clone of {} whose changes could be pushed",
+            self.url
+        ))?;
+        let branch = self.push_branch(&repo)?;
+        let metadata = CacheMetadata::load(&path);
+
+        let commit_options = CommitOptions {
+            sign: commit_options.sign || self.require_signed_commits.is_some(),
+            ..commit_options.clone()
+        };
+        let store_dir = self.subdir()?.unwrap_or_else(|| Path::new(""));
+        let commit = match git_util::do_git_commit(
+            &repo,
+            store_dir,
+            metadata.checked_out_paths.as_deref(),
+            &commit_options,
+        )
+        .context(format!("Could not commit changes of {}", self.url))?
+        {
+            None => {
+                log::info!(
+                    "There are no changed entries in the cached clone of {}",
+                    self.url
+                );
+                return Ok(());
+            }
+            Some(commit) => commit,
+        };
+
+        let push_url = url_rewrite::apply_overrides(&self.url)?;
+        git_util::do_git_push(&repo, &push_url, &branch, commit, &git_options)
+            .context(format!("Could not push changes to {}", self.url))?;
+        log::info!(
+            "Pushed commit {} to branch {} of {}",
+            commit,
+            branch,
+            self.url
+        );
+        Ok(())
+    }
+
+    /// Clone or fetch the cached repository as necessary and resolve *revision* in it
+    ///
+    /// The locked clone is returned together with its path, its metadata and the resolved commit whose signatures
+    /// have already been verified.
+    fn update_cache(
+        &self,
+        revision: GitRevision,
+        ignore_ttl: bool,
+        results: &mut ResultCollector,
+    ) -> anyhow::Result<(PathBuf, Repository, CacheMetadata, Oid)> {
         let path = self.unique_path()?;
-        match Repository::open(&path) {
-            // repo exists, so do fetch
-            Ok(repo) => git_util::do_git_pull(&repo).and(Ok(path)),
+        clone_lock::lock_clone(&path)?;
+        let mut metadata = CacheMetadata::load(&path);
+        let repo = match self.open_cached(&path) {
+            // repo exists, so fetch it if necessary
+            Some(repo) => {
+                self.fetch_if_necessary(&repo, revision, &mut metadata, ignore_ttl, results)?;
+                repo
+            }
             // repo does not exist, so clone it
-            Err(_) => git_util::do_git_clone(&self.url, &path).and(Ok(path)),
+            None => self.clone_to(&path, &mut metadata, results)?,
+        };
+
+        let commit = self.resolve_revision(&repo, revision, results)?;
+        self.verify_signatures(&repo, commit, &mut metadata, results)?;
+        Ok((path, repo, metadata, commit))
+    }
+
+    /// Setup the cached clone so that *revision* is checked out and return the store directory and commit
+    ///
+    /// Only *sparse_paths* are checked out if they are given.
+    fn setup_revision(
+        &self,
+        revision: GitRevision,
+        ignore_ttl: bool,
+        sparse_paths: Option<&[String]>,
+        results: &mut ResultCollector,
+    ) -> anyhow::Result<(PathBuf, Oid)> {
+        let subdir = self.subdir()?;
+        let git_options = self.git_options()?;
+        let (path, repo, mut metadata, mut commit) =
+            self.update_cache(revision, ignore_ttl, results)?;
+
+        // the checkout resets the working directory, so entries which were changed locally would be lost
+        let changed_entries = git_util::changed_entries(
+            &repo,
+            subdir.unwrap_or_else(|| Path::new("")),
+            metadata.checked_out_paths.as_deref(),
+        )
+        .context(format!(
+            "Could not check the cached clone at {} for changed entries",
+            path.display()
+        ))?;
+        if !changed_entries.is_empty() {
+            bail!(
+                "The cached clone of {} at {} contains changed entries which would be discarded: {}\n\
+                Push them with `kustomize-pass push` or discard them with `kustomize-pass cache clear`",
+                self.url,
+                path.display(),
+                changed_entries
+                    .iter()
+                    .map(|(_, entry)| entry.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
         }
+
+        // check out the requested revision while treating a failure as sign of a corrupted cache
+        if let Err(e) = git_util::do_git_checkout(&repo, commit, sparse_paths, &git_options) {
+            if env_config::offline() {
+                return Err(e);
+            }
+            log::warn!(
+                "Could not check out {} from cached clone at {}, cloning it again: {:#}",
+                revision,
+                path.display(),
+                e
+            );
+            let repo = self.clone_to(&path, &mut metadata, results)?;
+            commit = self.resolve_revision(&repo, revision, results)?;
+            self.verify_signatures(&repo, commit, &mut metadata, results)?;
+            git_util::do_git_checkout(&repo, commit, sparse_paths, &git_options)?;
+        }
+        metadata.record_use(&self.url);
+        metadata.checked_out_paths = sparse_paths.map(<[String]>::to_vec);
+        metadata.store(&path)?;
+
+        // without any checked out entries, the configured subdirectory does not exist but is still a valid store
+        if let (Some([]), Some(subdir)) = (sparse_paths, subdir) {
+            fs::create_dir_all(path.join(subdir))?;
+        }
+
+        // the password store is located inside the configured subdirectory of the repository
+        let store_dir = store_dir(path, subdir, &format!("Repository {}", self.url))?;
+        Ok((store_dir, commit))
     }
 }
@@ -0,0 +1,43 @@
+use crate::k8s_types::pass_secret::ApiVersion;
+use crate::k8s_types::{K8sObjectMeta, K8sTypeId, PassSource};
+use okapi::schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// The concrete type that is used to configure this application as a KRM transformer function
+///
+/// When configured with this object, every string field of every resource that is passed through the
+/// transformer is inspected.
+/// Values which start with the configured `prefix` are interpreted as placeholders and replaced by the content
+/// of the referenced pass entry (e.g. `pass:db/prod/password`).
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct V1Beta1PassTransformer {
+    /// Type identification information as apiVersion and kind
+    #[serde(flatten)]
+    pub k8s_type_id: K8sTypeId<ApiVersion, PassTransformerKind>,
+
+    /// Standard object's metadata
+    pub metadata: K8sObjectMeta,
+
+    /// Reference to the store from which placeholder values should be retrieved
+    #[serde(default)]
+    pub source: PassSource,
+
+    /// The prefix which marks a string value as placeholder.
+    ///
+    /// Everything following the prefix is used as the name of the entry in pass.
+    /// Defaults to `pass:`.
+    #[serde(default = "default_prefix")]
+    pub prefix: String,
+}
+
+fn default_prefix() -> String {
+    "pass:".to_string()
+}
+
+/// Possible values for `PassTransformer`s kind field
+#[derive(Copy, Clone, Debug, Hash, Serialize, Deserialize, Eq, PartialEq, JsonSchema)]
+pub enum PassTransformerKind {
+    #[serde(rename = "PassTransformer")]
+    PassTransformer,
+}
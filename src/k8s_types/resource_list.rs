@@ -1,5 +1,4 @@
-use crate::k8s_types::krm_result::KrmResult;
-use crate::k8s_types::K8sTypeId;
+use crate::k8s_types::{K8sTypeId, KrmResult};
 use serde::{Deserialize, Serialize};
 use serde_yaml::{Mapping, Value};
 
@@ -26,6 +25,7 @@ pub struct V1ResourceList {
     pub function_config: Option<Mapping>,
 
     /// An optional list that can be used by KRM functions to emit results for observability and debugging purposes
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub results: Option<Vec<KrmResult>>,
 }
 
@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
 
 /// Necessary metadata for referencing a Kubernetes object
 #[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone, Hash)]
@@ -11,8 +12,27 @@ pub struct ResourceRef {
     pub kind: String,
 
     /// Namespace in which the referenced object lives
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub namespace: Option<String>,
 
     /// Name of the referenced object
     pub name: String,
 }
+
+impl ResourceRef {
+    /// Construct a reference to the given unstructured kubernetes object
+    ///
+    /// Returns `None` if the object does not contain the necessary type and metadata fields
+    pub fn from_resource(resource: &Value) -> Option<Self> {
+        let metadata = resource.get("metadata")?;
+        Some(Self {
+            api_version: resource.get("apiVersion")?.as_str()?.to_string(),
+            kind: resource.get("kind")?.as_str()?.to_string(),
+            namespace: metadata
+                .get("namespace")
+                .and_then(Value::as_str)
+                .map(str::to_string),
+            name: metadata.get("name")?.as_str()?.to_string(),
+        })
+    }
+}
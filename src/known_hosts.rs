@@ -0,0 +1,426 @@
+//! Verification of ssh host keys against `known_hosts` files in the format used by OpenSSH
+use directories::UserDirs;
+use hmac::digest::KeyInit;
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::PathBuf;
+
+/// Port which is used by ssh if none is given explicitly
+pub(crate) const DEFAULT_SSH_PORT: u16 = 22;
+
+/// known_hosts file which is shared by all users of the system
+const GLOBAL_KNOWN_HOSTS: &str = "/etc/ssh/ssh_known_hosts";
+
+/// A single line of a known_hosts file
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct KnownHost {
+    /// Whether the key is marked with `@revoked`
+    revoked: bool,
+    /// Comma separated host patterns or a single hashed host name
+    patterns: String,
+    /// Type of the key, e.g. `ssh-ed25519`
+    key_type: String,
+    /// The raw public key
+    key: Vec<u8>,
+}
+
+/// The content of one or more known_hosts files
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub(crate) struct KnownHosts {
+    entries: Vec<KnownHost>,
+    /// Host patterns of `@cert-authority` lines which are not supported but used to explain failures
+    cert_authority_patterns: Vec<String>,
+}
+
+/// Reason why a host key could not be verified
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) enum HostKeyError {
+    /// There is no known_hosts entry for the host at all
+    Unknown { host: String },
+    /// The host is only trusted through an unsupported `@cert-authority` entry
+    CertAuthorityOnly { host: String },
+    /// The host is known but only with keys of other types than the presented one
+    UnknownKeyType { host: String, key_type: String },
+    /// The host is known but presented a different key of the same type
+    Mismatch { host: String, key_type: String },
+    /// The presented key is explicitly marked as revoked
+    Revoked { host: String, key_type: String },
+}
+
+impl Display for HostKeyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HostKeyError::Unknown { host } => write!(
+                f,
+                "Host key verification failed: {host} is not contained in any known_hosts file or the knownHosts field of the source"
+            ),
+            HostKeyError::CertAuthorityOnly { host } => write!(
+                f,
+                "Host key verification failed: {host} is only trusted through @cert-authority entries which are not supported, its host key must be added to a known_hosts file or the knownHosts field of the source"
+            ),
+            HostKeyError::UnknownKeyType { host, key_type } => write!(
+                f,
+                "Host key verification failed: the known_hosts entries of {host} do not contain a {key_type} key like the one it presented"
+            ),
+            HostKeyError::Mismatch { host, key_type } => write!(
+                f,
+                "Host key verification failed: the {key_type} key presented by {host} does not match its known_hosts entries, someone could be impersonating the host"
+            ),
+            HostKeyError::Revoked { host, key_type } => write!(
+                f,
+                "Host key verification failed: the {key_type} key presented by {host} is marked as revoked"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for HostKeyError {}
+
+impl KnownHosts {
+    /// Parse the content of a known_hosts file while ignoring comments and invalid lines
+    ///
+    /// `@cert-authority` lines are not supported, so they are only remembered to explain verification failures.
+    pub(crate) fn parse(content: &str) -> Self {
+        let mut known_hosts = Self::default();
+        let lines = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'));
+        for line in lines {
+            let mut fields = line.split_whitespace().peekable();
+            let marker = fields.next_if(|field| field.starts_with('@'));
+            let (patterns, key_type, key) = match (fields.next(), fields.next(), fields.next()) {
+                (Some(patterns), Some(key_type), Some(key)) => (patterns, key_type, key),
+                _ => continue,
+            };
+            if marker == Some("@cert-authority") {
+                log::debug!("Ignoring unsupported known_hosts line: {}", line);
+                known_hosts
+                    .cert_authority_patterns
+                    .push(patterns.to_string());
+                continue;
+            }
+            if let Ok(key) = base64::decode(key) {
+                known_hosts.entries.push(KnownHost {
+                    revoked: marker == Some("@revoked"),
+                    patterns: patterns.to_string(),
+                    key_type: key_type.to_string(),
+                    key,
+                });
+            }
+        }
+        known_hosts
+    }
+
+    /// Load the known_hosts files of the current user and of the system with *additional* entries added to them
+    pub(crate) fn load(additional: Option<&str>) -> Self {
+        let mut paths = vec![PathBuf::from(GLOBAL_KNOWN_HOSTS)];
+        if let Some(dirs) = UserDirs::new() {
+            paths.push(dirs.home_dir().join(".ssh").join("known_hosts"));
+        }
+
+        let mut known_hosts = Self::parse(additional.unwrap_or_default());
+        for path in paths {
+            if let Ok(content) = fs::read_to_string(&path) {
+                log::trace!("Using known hosts from {}", path.display());
+                let parsed = Self::parse(&content);
+                known_hosts.entries.extend(parsed.entries);
+                known_hosts
+                    .cert_authority_patterns
+                    .extend(parsed.cert_authority_patterns);
+            }
+        }
+        known_hosts
+    }
+
+    /// Verify that *key* is a known key of *host*
+    ///
+    /// *key* is checked through *matches_key* which receives each candidate key blob so that callers can compare
+    /// either the raw key or one of its hashes.
+    /// Only entries of *key_type* are considered to be a mismatch so that hosts which are only known with other
+    /// types of keys are reported separately.
+    /// If the type of the key is unknown, entries of all types are considered.
+    pub(crate) fn verify(
+        &self,
+        host: &str,
+        port: u16,
+        key_type: Option<&str>,
+        matches_key: impl Fn(&[u8]) -> bool,
+    ) -> Result<(), HostKeyError> {
+        // ssh stores hosts on non-standard ports as [host]:port
+        let host_name = match port {
+            DEFAULT_SSH_PORT => host.to_string(),
+            port => format!("[{host}]:{port}"),
+        };
+        let key_type_name = key_type.unwrap_or("unknown").to_string();
+
+        let entries = self
+            .entries
+            .iter()
+            .filter(|entry| host_matches(&entry.patterns, &host_name))
+            .collect::<Vec<_>>();
+        if entries
+            .iter()
+            .any(|entry| entry.revoked && matches_key(&entry.key))
+        {
+            return Err(HostKeyError::Revoked {
+                host: host_name,
+                key_type: key_type_name,
+            });
+        }
+        if entries
+            .iter()
+            .any(|entry| !entry.revoked && matches_key(&entry.key))
+        {
+            log::debug!("Host key of {} is known", host_name);
+            return Ok(());
+        }
+
+        let mut valid_entries = entries.iter().filter(|entry| !entry.revoked).peekable();
+        if valid_entries.peek().is_none() {
+            let is_cert_authority_host = self
+                .cert_authority_patterns
+                .iter()
+                .any(|patterns| host_matches(patterns, &host_name));
+            match is_cert_authority_host {
+                true => Err(HostKeyError::CertAuthorityOnly { host: host_name }),
+                false => Err(HostKeyError::Unknown { host: host_name }),
+            }
+        } else if valid_entries
+            .any(|entry| key_type.is_none_or(|key_type| entry.key_type == key_type))
+        {
+            Err(HostKeyError::Mismatch {
+                host: host_name,
+                key_type: key_type_name,
+            })
+        } else {
+            Err(HostKeyError::UnknownKeyType {
+                host: host_name,
+                key_type: key_type_name,
+            })
+        }
+    }
+}
+
+/// Compute the sha256 digest of a raw public key as it is reported for ssh connections
+pub(crate) fn key_sha256(key: &[u8]) -> [u8; 32] {
+    Sha256::digest(key).into()
+}
+
+/// Whether *host_name* is matched by the host *patterns* of a known_hosts line
+fn host_matches(patterns: &str, host_name: &str) -> bool {
+    // hashed host names have the form |1|<base64 salt>|<base64 hmac-sha1 of the host name>
+    if let Some(hashed) = patterns.strip_prefix("|1|") {
+        return hashed
+            .split_once('|')
+            .and_then(|(salt, hash)| Some((base64::decode(salt).ok()?, base64::decode(hash).ok()?)))
+            .and_then(|(salt, hash)| {
+                let mut mac = <Hmac<Sha1> as KeyInit>::new_from_slice(&salt).ok()?;
+                mac.update(host_name.as_bytes());
+                Some(mac.verify_slice(&hash).is_ok())
+            })
+            .unwrap_or(false);
+    }
+
+    // a negated pattern excludes the host even if another pattern matches
+    let mut matched = false;
+    for pattern in patterns.split(',') {
+        match pattern.strip_prefix('!') {
+            Some(pattern) if wildcard_matches(pattern, host_name) => return false,
+            Some(_) => {}
+            None => matched |= wildcard_matches(pattern, host_name),
+        }
+    }
+    matched
+}
+
+/// Whether *value* is matched by *pattern* which may contain the wildcards `*` and `?`
+fn wildcard_matches(pattern: &str, value: &str) -> bool {
+    fn matches(pattern: &[u8], value: &[u8]) -> bool {
+        match (pattern.first(), value.first()) {
+            (None, None) => true,
+            (Some(b'*'), _) => {
+                matches(&pattern[1..], value)
+                    || (!value.is_empty() && matches(pattern, &value[1..]))
+            }
+            (Some(b'?'), Some(_)) => matches(&pattern[1..], &value[1..]),
+            (Some(p), Some(v)) if p.eq_ignore_ascii_case(v) => matches(&pattern[1..], &value[1..]),
+            _ => false,
+        }
+    }
+    matches(pattern.as_bytes(), value.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Raw key of the ed25519 known_hosts entries below
+    const ED25519_KEY: &str =
+        "AAAAC3NzaC1lZDI1NTE5AAAAIJCoxWzLGavPe1OrdzOvdTLqbIhbwHY7QBqzazU30EuB";
+
+    /// A different raw key which is used as rsa key
+    const RSA_KEY: &str = "AAAAB3NzaC1yc2EAAAADAQABAAAAgQC7";
+
+    fn verify(
+        known_hosts: &str,
+        host: &str,
+        port: u16,
+        key_type: &str,
+        key: &str,
+    ) -> Result<(), HostKeyError> {
+        let key = base64::decode(key).unwrap();
+        KnownHosts::parse(known_hosts)
+            .verify(host, port, Some(key_type), |candidate| candidate == key)
+    }
+
+    #[test]
+    fn plain_host_names_match() {
+        let known_hosts = format!("# comment\nexample.com ssh-ed25519 {ED25519_KEY} comment\n");
+        assert_eq!(
+            verify(&known_hosts, "example.com", 22, "ssh-ed25519", ED25519_KEY),
+            Ok(())
+        );
+        assert_eq!(
+            verify(&known_hosts, "other.com", 22, "ssh-ed25519", ED25519_KEY),
+            Err(HostKeyError::Unknown {
+                host: "other.com".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn hashed_host_names_match() {
+        // created by `ssh-keygen -H` for example.com and [example.com]:2222
+        let known_hosts = format!(
+            "|1|MMrjmtogSWfWqZVW/6h6Q9qxWEw=|LpLCvBi91TrsWlZ0PS93S1ozfOE= ssh-ed25519 {ED25519_KEY}\n\
+            |1|B8KxQ/AlYsFVrHxtOKTNZffXdLk=|kdMekS34GyMa4JJZqYHyLyVOXTk= ssh-ed25519 {ED25519_KEY}\n"
+        );
+        assert_eq!(
+            verify(&known_hosts, "example.com", 22, "ssh-ed25519", ED25519_KEY),
+            Ok(())
+        );
+        assert_eq!(
+            verify(
+                &known_hosts,
+                "example.com",
+                2222,
+                "ssh-ed25519",
+                ED25519_KEY
+            ),
+            Ok(())
+        );
+        assert!(verify(&known_hosts, "example.org", 22, "ssh-ed25519", ED25519_KEY).is_err());
+    }
+
+    #[test]
+    fn non_standard_ports_are_matched_in_brackets() {
+        let known_hosts = format!("[example.com]:2222 ssh-ed25519 {ED25519_KEY}\n");
+        assert_eq!(
+            verify(
+                &known_hosts,
+                "example.com",
+                2222,
+                "ssh-ed25519",
+                ED25519_KEY
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            verify(&known_hosts, "example.com", 22, "ssh-ed25519", ED25519_KEY),
+            Err(HostKeyError::Unknown {
+                host: "example.com".to_string()
+            })
+        );
+        assert_eq!(
+            verify(
+                &known_hosts,
+                "example.com",
+                2223,
+                "ssh-ed25519",
+                ED25519_KEY
+            ),
+            Err(HostKeyError::Unknown {
+                host: "[example.com]:2223".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn wildcards_and_negations_are_applied() {
+        let known_hosts =
+            format!("*.example.com,git?.example.org,!bad.example.com ssh-ed25519 {ED25519_KEY}\n");
+        for host in ["git.example.com", "a.b.example.com", "git1.example.org"] {
+            assert_eq!(
+                verify(&known_hosts, host, 22, "ssh-ed25519", ED25519_KEY),
+                Ok(()),
+                "{host}"
+            );
+        }
+        for host in ["example.com", "bad.example.com", "git12.example.org"] {
+            assert!(
+                verify(&known_hosts, host, 22, "ssh-ed25519", ED25519_KEY).is_err(),
+                "{host}"
+            );
+        }
+    }
+
+    #[test]
+    fn revoked_keys_are_rejected() {
+        let known_hosts = format!(
+            "example.com ssh-ed25519 {ED25519_KEY}\n@revoked * ssh-ed25519 {ED25519_KEY}\n"
+        );
+        assert_eq!(
+            verify(&known_hosts, "example.com", 22, "ssh-ed25519", ED25519_KEY),
+            Err(HostKeyError::Revoked {
+                host: "example.com".to_string(),
+                key_type: "ssh-ed25519".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn mismatches_are_distinguished_by_key_type() {
+        let known_hosts = format!("example.com ssh-ed25519 {ED25519_KEY}\n");
+        assert_eq!(
+            verify(&known_hosts, "example.com", 22, "ssh-ed25519", RSA_KEY),
+            Err(HostKeyError::Mismatch {
+                host: "example.com".to_string(),
+                key_type: "ssh-ed25519".to_string()
+            })
+        );
+        assert_eq!(
+            verify(&known_hosts, "example.com", 22, "ssh-rsa", RSA_KEY),
+            Err(HostKeyError::UnknownKeyType {
+                host: "example.com".to_string(),
+                key_type: "ssh-rsa".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn cert_authorities_explain_unknown_hosts() {
+        let known_hosts = format!("@cert-authority *.example.com ssh-ed25519 {ED25519_KEY}\n");
+        assert_eq!(
+            verify(
+                &known_hosts,
+                "git.example.com",
+                22,
+                "ssh-ed25519",
+                ED25519_KEY
+            ),
+            Err(HostKeyError::CertAuthorityOnly {
+                host: "git.example.com".to_string()
+            })
+        );
+        assert_eq!(
+            verify(&known_hosts, "example.org", 22, "ssh-ed25519", ED25519_KEY),
+            Err(HostKeyError::Unknown {
+                host: "example.org".to_string()
+            })
+        );
+    }
+}
@@ -0,0 +1,101 @@
+//! The `kustomize-pass.lock` file which records the exact commits of git sources
+//!
+//! If a lockfile is present in the current directory (which is the directory of the kustomization when run by
+//! kustomize), git sources check out the recorded commit instead of resolving their revision again.
+use crate::git_util::GitRevision;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// Name of the lockfile which is placed next to the kustomization
+pub(crate) const LOCKFILE_NAME: &str = "kustomize-pass.lock";
+
+/// Comment which is placed at the top of generated lockfiles
+const LOCKFILE_HEADER: &str =
+    "# This file is generated by `kustomize-pass update-lock` and should not be edited manually\n";
+
+/// Content of a lockfile
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Lockfile {
+    /// The locked commit of each git source
+    #[serde(default)]
+    pub(crate) sources: Vec<LockedSource>,
+}
+
+/// The commit that is used for one git source
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct LockedSource {
+    /// Clone url of the source
+    pub(crate) url: String,
+
+    /// The revision of the source that was resolved or none for the default branch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) revision: Option<String>,
+
+    /// Full hash of the commit that the revision resolved to
+    pub(crate) commit: String,
+}
+
+/// Describe *revision* the way it is stored in the lockfile
+fn revision_key(revision: GitRevision) -> Option<String> {
+    match revision {
+        GitRevision::DefaultBranch => None,
+        revision => Some(revision.to_string()),
+    }
+}
+
+impl Lockfile {
+    /// Load the lockfile at *path* if it exists
+    pub(crate) fn load(path: &Path) -> anyhow::Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        log::debug!("Using lockfile {}", path.display());
+        let content = fs::read_to_string(path)
+            .context(format!("Could not read lockfile {}", path.display()))?;
+        let lockfile = serde_yaml::from_str(&content)
+            .context(format!("Could not parse lockfile {}", path.display()))?;
+        Ok(Some(lockfile))
+    }
+
+    /// Load the lockfile from the current directory if it exists
+    pub(crate) fn load_current() -> anyhow::Result<Option<Self>> {
+        Self::load(Path::new(LOCKFILE_NAME))
+    }
+
+    /// Store the lockfile at *path*
+    pub(crate) fn store(&self, path: &Path) -> anyhow::Result<()> {
+        let content = LOCKFILE_HEADER.to_owned() + &serde_yaml::to_string(self)?;
+        fs::write(path, content).context(format!("Could not write lockfile {}", path.display()))
+    }
+
+    /// The commit that is locked for *revision* of the source at *url*
+    pub(crate) fn locked_commit(&self, url: &str, revision: GitRevision) -> Option<&str> {
+        let revision = revision_key(revision);
+        self.sources
+            .iter()
+            .find(|source| source.url == url && source.revision == revision)
+            .map(|source| source.commit.as_str())
+    }
+
+    /// Lock *revision* of the source at *url* to *commit*
+    pub(crate) fn lock(&mut self, url: &str, revision: GitRevision, commit: String) {
+        let revision = revision_key(revision);
+        match self
+            .sources
+            .iter_mut()
+            .find(|source| source.url == url && source.revision == revision)
+        {
+            Some(source) => source.commit = commit,
+            None => self.sources.push(LockedSource {
+                url: url.to_string(),
+                revision,
+                commit,
+            }),
+        }
+    }
+}
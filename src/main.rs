@@ -13,14 +13,32 @@
     unused_qualifications
 )]
 
+mod archive;
+mod cache;
+mod cache_metadata;
 pub mod cli;
+mod clone_lock;
+mod commit_signatures;
+mod env_config;
 mod exec_krm_function;
+mod git_cli;
 mod git_util;
 pub mod k8s_types;
+mod known_hosts;
+mod lockfile;
+mod netrc;
+mod password_store;
+mod placeholder_replacement;
 mod print_schema;
+mod push_changes;
+mod results;
 mod secret_conversion;
+mod totp;
+mod update_lock;
+mod url_rewrite;
+mod value_selection;
 
-use crate::k8s_types::{V1Beta1PassSecret, V1Secret};
+use crate::k8s_types::V1Beta1PassSecret;
 use clap::Parser;
 use cli::*;
 use k8s_types::V1ResourceList;
@@ -38,6 +56,25 @@ fn main() {
                 SchemaFormat::JsonSchema => print_schema::print_json_schema(),
                 SchemaFormat::Openapi => print_schema::print_openapi_schema(),
             },
+            CliCommand::UpdateLock { files } => update_lock::update_lock(&files),
+            CliCommand::Push {
+                files,
+                message,
+                sign,
+                signing_key,
+            } => push_changes::push_changes(
+                &files,
+                &git_util::CommitOptions {
+                    message,
+                    sign,
+                    signing_key,
+                },
+            ),
+            CliCommand::Cache { command } => match command {
+                CacheCommand::List => cache::list_cache(),
+                CacheCommand::Prune { days } => cache::prune_cache(days),
+                CacheCommand::Clear => cache::clear_cache(),
+            },
         },
     };
 
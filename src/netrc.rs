@@ -0,0 +1,179 @@
+//! Lookup of credentials in the `~/.netrc` file as it is also used by curl and ftp
+use directories::UserDirs;
+use std::fs;
+
+/// Credentials for one machine from a netrc file
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub(crate) struct NetrcCredentials {
+    /// The user name or none if the entry does not contain one
+    pub(crate) login: Option<String>,
+
+    /// The password
+    pub(crate) password: String,
+}
+
+/// Split *content* into its tokens while skipping the bodies of macro definitions
+///
+/// A macro definition is terminated by an empty line.
+fn tokens(content: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut in_macro = false;
+    for line in content.lines() {
+        if in_macro {
+            in_macro = !line.trim().is_empty();
+            continue;
+        }
+        let mut line_tokens = line.split_whitespace();
+        while let Some(token) = line_tokens.next() {
+            if token == "macdef" {
+                line_tokens.next();
+                in_macro = true;
+                break;
+            }
+            tokens.push(token);
+        }
+    }
+    tokens
+}
+
+/// Find the credentials for *host* in the netrc *content*
+///
+/// The first `machine` entry for *host* is used or the `default` entry if there is none.
+pub(crate) fn parse(content: &str, host: &str) -> Option<NetrcCredentials> {
+    let mut matched = None;
+    let mut default = None;
+    let mut current: Option<(bool, Option<String>, Option<String>)> = None;
+
+    let mut tokens = tokens(content).into_iter();
+    while let Some(token) = tokens.next() {
+        match token {
+            "machine" | "default" => {
+                if let Some((is_default, login, Some(password))) = current.take() {
+                    let credentials = NetrcCredentials { login, password };
+                    match is_default {
+                        false => return Some(credentials),
+                        true => default = default.or(Some(credentials)),
+                    }
+                }
+                current = match token {
+                    "machine" => tokens
+                        .next()
+                        .filter(|machine| machine.eq_ignore_ascii_case(host))
+                        .map(|_| (false, None, None)),
+                    _ => Some((true, None, None)),
+                };
+            }
+            "login" | "password" | "account" => {
+                let value = tokens.next().map(str::to_string);
+                match (token, current.as_mut()) {
+                    ("login", Some((_, login, _))) => *login = value,
+                    ("password", Some((_, _, password))) => *password = value,
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+    if let Some((is_default, login, Some(password))) = current {
+        let credentials = NetrcCredentials { login, password };
+        match is_default {
+            false => matched = Some(credentials),
+            true => default = default.or(Some(credentials)),
+        }
+    }
+    matched.or(default)
+}
+
+/// Find the credentials for *host* in `~/.netrc`
+pub(crate) fn lookup(host: &str) -> Option<NetrcCredentials> {
+    let path = UserDirs::new()?.home_dir().join(".netrc");
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) => {
+            log::trace!("Could not read {}: {}", path.display(), e);
+            return None;
+        }
+    };
+    parse(&content, host)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn credentials(login: Option<&str>, password: &str) -> Option<NetrcCredentials> {
+        Some(NetrcCredentials {
+            login: login.map(str::to_string),
+            password: password.to_string(),
+        })
+    }
+
+    #[test]
+    fn machine_entries_are_matched_by_host() {
+        let content = "machine github.com\n  login alice\n  password secret1\n\
+                       machine gitlab.com\n  login bob\n  password secret2\n";
+        assert_eq!(
+            parse(content, "github.com"),
+            credentials(Some("alice"), "secret1")
+        );
+        assert_eq!(
+            parse(content, "GitLab.com"),
+            credentials(Some("bob"), "secret2")
+        );
+        assert_eq!(parse(content, "example.com"), None);
+    }
+
+    #[test]
+    fn default_is_only_used_without_matching_machine() {
+        let content = "default login anonymous password guest\n\
+                       machine github.com login alice password secret\n";
+        assert_eq!(
+            parse(content, "github.com"),
+            credentials(Some("alice"), "secret")
+        );
+        assert_eq!(
+            parse(content, "example.com"),
+            credentials(Some("anonymous"), "guest")
+        );
+    }
+
+    #[test]
+    fn login_and_password_may_be_in_any_order() {
+        let content = "machine github.com password secret login alice\n\
+                       machine gitlab.com password token\n";
+        assert_eq!(
+            parse(content, "github.com"),
+            credentials(Some("alice"), "secret")
+        );
+        assert_eq!(parse(content, "gitlab.com"), credentials(None, "token"));
+    }
+
+    #[test]
+    fn several_machines_may_be_on_one_line() {
+        let content =
+            "machine a.com login a password pa machine b.com login b account x password pb\n";
+        assert_eq!(parse(content, "a.com"), credentials(Some("a"), "pa"));
+        assert_eq!(parse(content, "b.com"), credentials(Some("b"), "pb"));
+    }
+
+    #[test]
+    fn macro_definitions_end_at_an_empty_line() {
+        let content = "macdef init\n\
+                       machine github.com login evil password evil\n\
+                       \n\
+                       machine github.com login alice password secret\n";
+        assert_eq!(
+            parse(content, "github.com"),
+            credentials(Some("alice"), "secret")
+        );
+    }
+
+    #[test]
+    fn entries_without_password_are_ignored() {
+        let content = "machine github.com login alice\ndefault login anonymous password guest\n";
+        assert_eq!(
+            parse(content, "github.com"),
+            credentials(Some("anonymous"), "guest")
+        );
+    }
+}
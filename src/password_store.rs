@@ -0,0 +1,50 @@
+//! Access to the entries of a password store in an explicitly given directory
+//!
+//! This replaces the lookup functions of libpass which always operate on the store that is configured through the
+//! process-global `PASSWORD_STORE_DIR` environment variable.
+use anyhow::{anyhow, bail};
+use libpass::{PassError, StoreFileRef};
+use std::path::{Component, Path, PathBuf};
+
+/// Handle to a password store that is located in a specific directory
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct PasswordStore {
+    /// Root directory of the store
+    dir: PathBuf,
+}
+
+impl PasswordStore {
+    /// Create a handle to the password store whose root is *dir*
+    pub(crate) fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// Root directory of the store
+    pub(crate) fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Retrieve the file entry identified by *pass_name*
+    ///
+    /// `pass_name` is a path to a password file relative to the store root, just like for
+    /// [`libpass::retrieve`].
+    pub(crate) fn retrieve(&self, pass_name: &str) -> anyhow::Result<StoreFileRef> {
+        let pass_name = pass_name.trim_start_matches('/');
+        if !Path::new(pass_name)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            bail!("Entry {pass_name} is not a path inside the password store");
+        }
+
+        // resolve paths that could possibly be meant by pass_name
+        let dir_path = self.dir.join(pass_name);
+        let file_path = self.dir.join(pass_name.to_string() + ".gpg");
+        match (dir_path.exists(), file_path.is_file()) {
+            (true, true) => Err(PassError::AmbiguousPassName(pass_name.to_string()).into()),
+            (false, false) => Err(PassError::EntryNotFound(pass_name.to_string()).into()),
+            (true, false) => Err(anyhow!("Entry is a directory")),
+            (false, true) => Ok(StoreFileRef { path: file_path }),
+        }
+    }
+}
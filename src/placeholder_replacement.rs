@@ -0,0 +1,233 @@
+use crate::k8s_types::{ResourceRef, V1Beta1PassTransformer};
+use crate::password_store::PasswordStore;
+use crate::results::{ErrorCollection, ErrorLocation, ResultCollector};
+use crate::secret_conversion::{convert_value, SecretValue};
+use crate::value_selection::PassReference;
+use anyhow::{anyhow, Context};
+use serde_yaml::Value;
+
+/// Whether *item* is a kubernetes Secret whose `data` values are expected to be base64 encoded
+fn is_v1_secret(item: &Value) -> bool {
+    item.get("apiVersion").and_then(Value::as_str) == Some("v1")
+        && item.get("kind").and_then(Value::as_str) == Some("Secret")
+}
+
+/// Resolve a single placeholder into the value from *store* that should be inserted in its place
+fn resolve_placeholder(
+    store: &PasswordStore,
+    pass_name: &str,
+    base64_encoded: bool,
+) -> anyhow::Result<String> {
+    match (convert_value(store, pass_name)?, base64_encoded) {
+        (SecretValue::String(value), false) => Ok(value),
+        (SecretValue::String(value), true) => Ok(base64::encode(value)),
+        (SecretValue::Binary(value), true) => Ok(value),
+        (SecretValue::Binary(_), false) => Err(anyhow!(
+            "Entry {pass_name} contains binary data which cannot be inserted into a string field"
+        )),
+    }
+}
+
+/// Recursively walk *value* and replace all strings that start with *prefix* by the value *resolve* returns for them
+///
+/// *resolve* receives the reference of the placeholder and whether its value must be base64 encoded.
+/// *path* is the path of *value* inside its resource and errors are collected into *errors* so that
+/// processing can continue with the remaining values.
+fn replace_in_value(
+    resolve: &dyn Fn(&str, bool) -> anyhow::Result<String>,
+    value: &mut Value,
+    path: &str,
+    prefix: &str,
+    base64_encoded: bool,
+    resource_ref: &Option<ResourceRef>,
+    errors: &mut Vec<anyhow::Error>,
+) {
+    match value {
+        Value::String(str_value) => {
+            if let Some(pass_name) = str_value.strip_prefix(prefix) {
+                log::debug!("Replacing placeholder for {} at {}", pass_name, path);
+                match resolve(pass_name, base64_encoded) {
+                    Ok(replacement) => *str_value = replacement,
+                    Err(e) => errors
+                        .push(e.context(ErrorLocation::resource_field(resource_ref.clone(), path))),
+                }
+            }
+        }
+        Value::Sequence(sequence) => {
+            for (i, i_value) in sequence.iter_mut().enumerate() {
+                let i_path = format!("{path}[{i}]");
+                replace_in_value(
+                    resolve,
+                    i_value,
+                    &i_path,
+                    prefix,
+                    base64_encoded,
+                    resource_ref,
+                    errors,
+                );
+            }
+        }
+        Value::Mapping(mapping) => {
+            for (i_key, i_value) in mapping.iter_mut() {
+                let i_path = format!("{path}.{}", key_to_path_segment(i_key));
+                replace_in_value(
+                    resolve,
+                    i_value,
+                    &i_path,
+                    prefix,
+                    base64_encoded,
+                    resource_ref,
+                    errors,
+                );
+            }
+        }
+        Value::Null | Value::Bool(_) | Value::Number(_) => {}
+    }
+}
+
+/// Recursively collect the names of all entries which are referenced by placeholders with *prefix* in *value*
+fn collect_placeholders<'a>(value: &'a Value, prefix: &str, pass_names: &mut Vec<&'a str>) {
+    match value {
+        Value::String(str_value) => {
+            if let Some(reference) = str_value.strip_prefix(prefix) {
+                if let Ok(reference) = PassReference::parse(reference) {
+                    pass_names.push(reference.pass_name);
+                }
+            }
+        }
+        Value::Sequence(sequence) => {
+            for i_value in sequence {
+                collect_placeholders(i_value, prefix, pass_names);
+            }
+        }
+        Value::Mapping(mapping) => {
+            for (_, i_value) in mapping.iter() {
+                collect_placeholders(i_value, prefix, pass_names);
+            }
+        }
+        Value::Null | Value::Bool(_) | Value::Number(_) => {}
+    }
+}
+
+/// Format a mapping key so that it can be used as a segment in a field path
+fn key_to_path_segment(key: &Value) -> String {
+    match key {
+        Value::String(key) => key.to_owned(),
+        _ => serde_yaml::to_string(key)
+            .map(|key| key.trim_start_matches("---").trim().to_string())
+            .unwrap_or_default(),
+    }
+}
+
+/// Replace all placeholders with *prefix* in copies of *items* by the values *resolve* returns for them
+///
+/// All errors are collected so that they can be reported together.
+/// Nothing is returned if any placeholder cannot be resolved so that no partially replaced item and none of the
+/// resolved secrets can end up in the output.
+fn replace_in_items(
+    items: &[Value],
+    prefix: &str,
+    resolve: &dyn Fn(&str, bool) -> anyhow::Result<String>,
+) -> anyhow::Result<Vec<Value>> {
+    let mut items = items.to_vec();
+    let mut errors = Vec::new();
+    for item in items.iter_mut() {
+        let is_secret = is_v1_secret(item);
+        let resource_ref = ResourceRef::from_resource(item);
+        if let Value::Mapping(mapping) = item {
+            for (i_key, i_value) in mapping.iter_mut() {
+                // values in a secrets data field must be base64 encoded while all others are inserted as-is
+                let base64_encoded = is_secret && i_key.as_str() == Some("data");
+                replace_in_value(
+                    resolve,
+                    i_value,
+                    &key_to_path_segment(i_key),
+                    prefix,
+                    base64_encoded,
+                    &resource_ref,
+                    &mut errors,
+                );
+            }
+        }
+    }
+    ErrorCollection(errors).into_result()?;
+    Ok(items)
+}
+
+/// Replace all placeholders in *items* with the referenced data from pass
+///
+/// *items* are left untouched if any placeholder cannot be replaced.
+/// Warnings which occur while setting up the source are reported to *results*.
+pub(crate) fn replace_placeholders(
+    config: V1Beta1PassTransformer,
+    items: &mut [Value],
+    results: &mut ResultCollector,
+) -> anyhow::Result<()> {
+    log::debug!(
+        "Replacing placeholders in resources with PassTransformer {}",
+        &config.metadata.name
+    );
+
+    // setup the password store source with the entries that are referenced by placeholders
+    let mut referenced_entries = Vec::new();
+    for item in items.iter() {
+        collect_placeholders(item, &config.prefix, &mut referenced_entries);
+    }
+    let store = config
+        .source
+        .setup(&referenced_entries, results)
+        .context(ErrorLocation::field("source"))?;
+
+    let replaced_items = replace_in_items(items, &config.prefix, &|pass_name, base64_encoded| {
+        resolve_placeholder(&store, pass_name, base64_encoded)
+    })?;
+    items.clone_from_slice(&replaced_items);
+
+    log::debug!(
+        "Done replacing placeholders with PassTransformer {}",
+        &config.metadata.name
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failed_placeholder_leaves_items_untouched() {
+        let items: Vec<Value> = serde_yaml::from_str(
+            "- apiVersion: v1\n  kind: ConfigMap\n  metadata: {name: config}\n  data:\n    known: pass:known\n    missing: pass:missing\n",
+        )
+        .unwrap();
+        let resolve = |pass_name: &str, _: bool| match pass_name {
+            "known" => Ok("decrypted-secret".to_string()),
+            _ => Err(anyhow!("Entry {pass_name} does not exist")),
+        };
+
+        let result = replace_in_items(&items, "pass:", &resolve);
+
+        let error = result.expect_err("a missing entry must fail the replacement");
+        assert!(format!("{error:#}").contains("Entry missing does not exist"));
+        assert!(!serde_yaml::to_string(&items)
+            .unwrap()
+            .contains("decrypted-secret"));
+    }
+
+    #[test]
+    fn placeholders_are_replaced_and_base64_encoded_in_secret_data() {
+        let items: Vec<Value> = serde_yaml::from_str(
+            "- apiVersion: v1\n  kind: Secret\n  metadata: {name: secret}\n  data: {key: pass:entry}\n  stringData: {key: pass:entry}\n",
+        )
+        .unwrap();
+        let resolve = |_: &str, base64_encoded: bool| match base64_encoded {
+            true => Ok(base64::encode("value")),
+            false => Ok("value".to_string()),
+        };
+
+        let items = replace_in_items(&items, "pass:", &resolve).unwrap();
+
+        assert_eq!(items[0]["data"]["key"], Value::from("dmFsdWU="));
+        assert_eq!(items[0]["stringData"]["key"], Value::from("value"));
+    }
+}
@@ -1,20 +1,39 @@
+use crate::k8s_types::V1Beta1PassTransformer;
 use crate::V1Beta1PassSecret;
 use okapi::{map, openapi3};
 use schemars::gen::{SchemaGenerator, SchemaSettings};
-use schemars::schema_for;
+use schemars::schema::{RootSchema, SchemaObject, SubschemaValidation};
 
 pub(crate) fn print_json_schema() -> anyhow::Result<()> {
-    log::debug!("Generating schema for PassSecret");
-    let schema = schema_for!(V1Beta1PassSecret);
+    log::debug!("Generating schema for PassSecret and PassTransformer");
+    let mut schema_generator = SchemaGenerator::default();
+    let any_of = vec![
+        schema_generator.subschema_for::<V1Beta1PassSecret>(),
+        schema_generator.subschema_for::<V1Beta1PassTransformer>(),
+    ];
+
+    // the root schema accepts any of the supported function configurations
+    let schema = RootSchema {
+        meta_schema: schema_generator.settings().meta_schema.clone(),
+        schema: SchemaObject {
+            subschemas: Some(Box::new(SubschemaValidation {
+                any_of: Some(any_of),
+                ..Default::default()
+            })),
+            ..Default::default()
+        },
+        definitions: schema_generator.take_definitions(),
+    };
 
     println!("{}", serde_json::to_string_pretty(&schema)?);
     Ok(())
 }
 
 pub(crate) fn print_openapi_schema() -> anyhow::Result<()> {
-    log::debug!("Generating Schema description for PassSecret");
+    log::debug!("Generating Schema description for PassSecret and PassTransformer");
     let mut schema_generator = SchemaGenerator::from(SchemaSettings::openapi3());
     schema_generator.subschema_for::<V1Beta1PassSecret>();
+    schema_generator.subschema_for::<V1Beta1PassTransformer>();
     let mut schema_definitions = schema_generator.take_definitions();
 
     // apply any modifications from registered visitors
@@ -0,0 +1,39 @@
+//! Writing changed entries of git sources back to their remotes
+use crate::git_util::CommitOptions;
+use crate::results::ErrorCollection;
+use crate::update_lock::{kustomization_config_files, sources_from_file};
+use anyhow::bail;
+use std::path::{Path, PathBuf};
+
+/// Commit and push the changed entries of all git sources of PassSecrets and PassTransformers contained in *file*
+fn push_changes_from_file(file: &Path, commit_options: &CommitOptions) -> anyhow::Result<()> {
+    log::debug!("Pushing changes of sources from {}", file.display());
+    for source in sources_from_file(file)? {
+        source.push_changes(commit_options)?;
+    }
+    Ok(())
+}
+
+/// Commit the entries which were changed in the cached clones of all git sources configured in *files* and push
+/// them to the remotes
+///
+/// If no files are given, the generators and transformers of the kustomization in the current directory are used.
+pub(crate) fn push_changes(
+    files: &[PathBuf],
+    commit_options: &CommitOptions,
+) -> anyhow::Result<()> {
+    let files = if files.is_empty() {
+        kustomization_config_files()?
+    } else {
+        files.to_vec()
+    };
+    if files.is_empty() {
+        bail!("There are no generators or transformers whose sources could be pushed");
+    }
+
+    let errors = files
+        .iter()
+        .filter_map(|file| push_changes_from_file(file, commit_options).err())
+        .collect();
+    ErrorCollection(errors).into_result()
+}
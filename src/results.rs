@@ -0,0 +1,139 @@
+//! Conversion of errors into structured results that are reported back to kustomize
+use crate::k8s_types::{FieldRef, KrmResult, ResourceRef, Severity};
+use std::fmt::{Display, Formatter};
+
+/// Collects the results which are reported during processing and are not caused by an error
+#[derive(Debug, Default)]
+pub(crate) struct ResultCollector {
+    results: Vec<KrmResult>,
+}
+
+impl ResultCollector {
+    /// Report a warning that should be included in the output of the function
+    ///
+    /// The warning is attributed to *field* of the resource that is currently being processed.
+    pub(crate) fn report_warning(&mut self, field: &str, message: String) {
+        log::warn!("{}", message);
+        let mut result = KrmResult::new(Severity::Warning, message);
+        result.field = Some(FieldRef::new(field));
+        self.results.push(result);
+    }
+
+    /// Turn the collector into the results that have been reported
+    ///
+    /// Results which are not attributed to a specific resource are attributed to *default_resource_ref* instead.
+    pub(crate) fn into_results(self, default_resource_ref: Option<&ResourceRef>) -> Vec<KrmResult> {
+        let mut results = self.results;
+        for result in results.iter_mut() {
+            if result.resource_ref.is_none() {
+                result.resource_ref = default_resource_ref.cloned();
+            }
+        }
+        results
+    }
+}
+
+/// Error context which attributes an error to a specific resource and/or field
+///
+/// It is attached to errors via [`anyhow::Context`] and later extracted again when the error is converted into
+/// a [`KrmResult`].
+#[derive(Debug, Clone)]
+pub(crate) struct ErrorLocation {
+    pub(crate) resource_ref: Option<ResourceRef>,
+    pub(crate) field: Option<String>,
+}
+
+impl ErrorLocation {
+    /// Attribute an error to a field of the resource that is currently being processed
+    pub(crate) fn field(field: impl Into<String>) -> Self {
+        Self {
+            resource_ref: None,
+            field: Some(field.into()),
+        }
+    }
+
+    /// Attribute an error to a field of the given resource
+    pub(crate) fn resource_field(
+        resource_ref: Option<ResourceRef>,
+        field: impl Into<String>,
+    ) -> Self {
+        Self {
+            resource_ref,
+            field: Some(field.into()),
+        }
+    }
+}
+
+impl Display for ErrorLocation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match (&self.field, &self.resource_ref) {
+            (Some(field), Some(resource_ref)) => write!(
+                f,
+                "Could not process field {} of {} {}",
+                field, resource_ref.kind, resource_ref.name
+            ),
+            (Some(field), None) => write!(f, "Could not process field {}", field),
+            (None, Some(resource_ref)) => write!(
+                f,
+                "Could not process {} {}",
+                resource_ref.kind, resource_ref.name
+            ),
+            (None, None) => write!(f, "Could not process input"),
+        }
+    }
+}
+
+/// Multiple independent errors which occurred during one operation and should all be reported
+#[derive(Debug)]
+pub(crate) struct ErrorCollection(pub(crate) Vec<anyhow::Error>);
+
+impl ErrorCollection {
+    /// Turn the collected errors into a result which is only `Ok` if no error was collected
+    pub(crate) fn into_result(mut self) -> anyhow::Result<()> {
+        match self.0.len() {
+            0 => Ok(()),
+            1 => Err(self.0.remove(0)),
+            _ => Err(anyhow::Error::new(self)),
+        }
+    }
+}
+
+impl Display for ErrorCollection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} errors occurred", self.0.len())?;
+        for error in self.0.iter() {
+            write!(f, "\n- {:#}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ErrorCollection {}
+
+/// Convert an error into structured results
+///
+/// Results which are not attributed to a specific resource by an [`ErrorLocation`] are attributed to
+/// *default_resource_ref* instead.
+pub(crate) fn results_from_error(
+    error: &anyhow::Error,
+    default_resource_ref: Option<&ResourceRef>,
+) -> Vec<KrmResult> {
+    match error.downcast_ref::<ErrorCollection>() {
+        Some(collection) => collection
+            .0
+            .iter()
+            .flat_map(|error| results_from_error(error, default_resource_ref))
+            .collect(),
+        None => {
+            let location = error.downcast_ref::<ErrorLocation>();
+            let mut result = KrmResult::new(Severity::Error, format!("{:#}", error));
+            result.resource_ref = location
+                .and_then(|location| location.resource_ref.clone())
+                .or_else(|| default_resource_ref.cloned());
+            result.field = location
+                .and_then(|location| location.field.as_deref())
+                .map(FieldRef::new);
+            vec![result]
+        }
+    }
+}
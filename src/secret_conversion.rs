@@ -1,15 +1,16 @@
 use crate::k8s_types::{GeneratorBehavior, V1Secret};
+use crate::password_store::PasswordStore;
+use crate::results::{ErrorCollection, ErrorLocation, ResultCollector};
+use crate::value_selection::{PassReference, Selector};
 use crate::V1Beta1PassSecret;
-use anyhow::{anyhow, bail, Context};
-use libpass::StoreEntry;
+use anyhow::{anyhow, Context};
 use std::collections::BTreeMap;
-use std::env;
 
 const BEHAVIOR_ANNOTATION: &str = "kustomize.config.k8s.io/behavior";
 
 /// An value that is encoded so that it cane easily be used as a value for Kubernetes Secrets
 #[derive(Debug, Clone, Eq, PartialEq)]
-enum SecretValue {
+pub(crate) enum SecretValue {
     /// Binary base64 encoded content
     Binary(String),
 
@@ -17,13 +18,21 @@ enum SecretValue {
     String(String),
 }
 
-fn convert_value(pass_name: &str) -> anyhow::Result<SecretValue> {
+/// Retrieve the value referenced by *reference* from *store*
+///
+/// See [`PassReference`] for the supported reference syntax.
+pub(crate) fn convert_value(store: &PasswordStore, reference: &str) -> anyhow::Result<SecretValue> {
+    let reference = PassReference::parse(reference)
+        .context(format!("Could not parse reference {reference}"))?;
+    let pass_name = reference.pass_name;
+
     // retrieve entry from store
-    log::debug!("Retrieving {} from pass", &pass_name);
-    let pass_entry = match libpass::retrieve(pass_name)? {
-        StoreEntry::Directory(_) => Err(anyhow!("Entry is a directory")),
-        StoreEntry::File(file) => Ok(file),
-    }?;
+    log::debug!(
+        "Retrieving {} from pass store {}",
+        &pass_name,
+        store.dir().display()
+    );
+    let pass_entry = store.retrieve(pass_name)?;
 
     // read and decrypt content from entry
     let bin_result = pass_entry
@@ -33,85 +42,105 @@ fn convert_value(pass_name: &str) -> anyhow::Result<SecretValue> {
         ))?
         .as_ref()
         .to_owned();
-    Ok(match String::from_utf8(bin_result) {
-        Ok(str_result) => SecretValue::String(
-            str_result
-                .strip_suffix('\n')
-                .map(|str_result| str_result.to_string())
-                .unwrap_or(str_result),
-        ),
-        Err(e) => SecretValue::Binary(base64::encode(e.as_bytes())),
-    })
+    match (String::from_utf8(bin_result), &reference.selector) {
+        (Ok(str_result), selector) => {
+            Ok(SecretValue::String(selector.select(&str_result).context(
+                format!("Could not select value from secret {pass_name}"),
+            )?))
+        }
+        (Err(e), Selector::Whole) => Ok(SecretValue::Binary(base64::encode(e.as_bytes()))),
+        (Err(_), _) => Err(anyhow!(
+            "Secret {pass_name} contains binary data so no value can be selected from it"
+        )),
+    }
 }
 
-impl TryFrom<V1Beta1PassSecret> for V1Secret {
-    type Error = anyhow::Error;
-
-    fn try_from(mut value: V1Beta1PassSecret) -> Result<Self, Self::Error> {
-        log::debug!(
-            "Trying to convert PassSecret {} to Secret",
-            &value.metadata.name
-        );
+/// Convert a PassSecret into a Secret which contains the referenced data from pass
+///
+/// Warnings which occur while setting up the source are reported to *results*.
+pub(crate) fn convert_pass_secret(
+    mut value: V1Beta1PassSecret,
+    results: &mut ResultCollector,
+) -> anyhow::Result<V1Secret> {
+    log::debug!(
+        "Trying to convert PassSecret {} to Secret",
+        &value.metadata.name
+    );
 
-        // setup the password store source
-        let store_dir = value.source.setup()?;
-        env::set_var(libpass::PASSWORD_STORE_DIR_ENV, store_dir);
+    // setup the password store source with the entries that are referenced by the data fields
+    let referenced_entries = value
+        .data
+        .values()
+        .filter_map(|reference| PassReference::parse(reference).ok())
+        .map(|reference| reference.pass_name)
+        .collect::<Vec<_>>();
+    let store = value
+        .source
+        .setup(&referenced_entries, results)
+        .context(ErrorLocation::field("source"))?;
 
-        // remove some internal annotations so that the secret doesn't get stripped out by kustomize
-        if let Some(ref mut annotations) = value.metadata.annotations {
-            annotations.remove("config.kubernetes.io/local-config");
-            annotations.remove("config.kubernetes.io/function");
-        }
+    // remove some internal annotations so that the secret doesn't get stripped out by kustomize
+    if let Some(ref mut annotations) = value.metadata.annotations {
+        annotations.remove("config.kubernetes.io/local-config");
+        annotations.remove("config.kubernetes.io/function");
+    }
 
-        // set an annotation to configure kustomize merge behavior
-        if value.behavior != GeneratorBehavior::default() {
-            match value.metadata.annotations {
-                Some(ref mut annotations) => {
-                    annotations.insert(BEHAVIOR_ANNOTATION.to_string(), value.behavior.to_string());
-                }
-                None => {
-                    let mut annotations = BTreeMap::new();
-                    annotations.insert(BEHAVIOR_ANNOTATION.to_string(), value.behavior.to_string());
-                    value.metadata.annotations = Some(annotations);
-                }
+    // set an annotation to configure kustomize merge behavior
+    if value.behavior != GeneratorBehavior::default() {
+        match value.metadata.annotations {
+            Some(ref mut annotations) => {
+                annotations.insert(BEHAVIOR_ANNOTATION.to_string(), value.behavior.to_string());
+            }
+            None => {
+                let mut annotations = BTreeMap::new();
+                annotations.insert(BEHAVIOR_ANNOTATION.to_string(), value.behavior.to_string());
+                value.metadata.annotations = Some(annotations);
             }
         }
+    }
 
-        // resolve all pass secrets
-        let mut str_results = BTreeMap::new();
-        let mut bin_results = BTreeMap::new();
-        for (i_key, i_value) in value.data.iter() {
-            match convert_value(i_value).context(format!(
-                "Could not convert PassSecret {} to Secret",
-                value.metadata.name
-            ))? {
-                SecretValue::String(result) => str_results.insert(i_key.to_owned(), result),
-                SecretValue::Binary(result) => bin_results.insert(i_key.to_owned(), result),
-            };
+    // resolve all pass secrets while collecting all errors so that they can be reported together
+    let mut str_results = BTreeMap::new();
+    let mut bin_results = BTreeMap::new();
+    let mut errors = Vec::new();
+    for (i_key, i_value) in value.data.iter() {
+        match convert_value(&store, i_value).context(ErrorLocation::field(format!("data.{i_key}")))
+        {
+            Ok(SecretValue::String(result)) => {
+                str_results.insert(i_key.to_owned(), result);
+            }
+            Ok(SecretValue::Binary(result)) => {
+                bin_results.insert(i_key.to_owned(), result);
+            }
+            Err(e) => errors.push(e),
         }
+    }
 
-        // copy plain_data into result
-        if let Some(plain_data) = value.plain_data {
-            for (key, value) in plain_data.iter() {
-                if str_results.contains_key(key) {
-                    bail!("plainData contains key {} but it is also retrieved from pass. This conflict cannot be resolved", key)
-                } else {
-                    str_results.insert(key.to_owned(), value.to_owned());
-                }
+    // copy plain_data into result
+    if let Some(plain_data) = value.plain_data {
+        for (key, plain_value) in plain_data.iter() {
+            if value.data.contains_key(key) {
+                errors.push(
+                    anyhow!("plainData contains key {} but it is also retrieved from pass. This conflict cannot be resolved", key)
+                        .context(ErrorLocation::field(format!("plainData.{key}"))),
+                );
+            } else {
+                str_results.insert(key.to_owned(), plain_value.to_owned());
             }
         }
-
-        // construct and return result
-        log::debug!(
-            "Done converting PassSecret {} to Secret",
-            &value.metadata.name
-        );
-        Ok(V1Secret::new(
-            value.metadata,
-            value.immutable,
-            value.secret_type,
-            str_results,
-            bin_results,
-        ))
     }
+    ErrorCollection(errors).into_result()?;
+
+    // construct and return result
+    log::debug!(
+        "Done converting PassSecret {} to Secret",
+        &value.metadata.name
+    );
+    Ok(V1Secret::new(
+        value.metadata,
+        value.immutable,
+        value.secret_type,
+        str_results,
+        bin_results,
+    ))
 }
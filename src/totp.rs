@@ -0,0 +1,240 @@
+//! Generation of time-based one-time passwords from `otpauth://` URIs as they are stored by
+//! [pass-otp](https://github.com/tadfisher/pass-otp)
+use anyhow::{anyhow, bail, Context};
+use hmac::digest::KeyInit;
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+use std::time::{SystemTime, UNIX_EPOCH};
+use url::Url;
+
+/// URI scheme which is used by otp entries
+pub(crate) const OTPAUTH_SCHEME: &str = "otpauth://";
+
+/// Hash algorithms which can be used for TOTP generation
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Algorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+/// Parameters of a TOTP generator as they are encoded in an `otpauth://totp/...` URI
+///
+/// See the [key uri format](https://github.com/google/google-authenticator/wiki/Key-Uri-Format) for details.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct TotpParameters {
+    secret: Vec<u8>,
+    algorithm: Algorithm,
+    digits: u32,
+    period: u64,
+}
+
+impl TotpParameters {
+    /// Parse TOTP parameters from an `otpauth://totp/...` URI
+    pub(crate) fn from_uri(uri: &str) -> anyhow::Result<Self> {
+        let uri = Url::parse(uri).context("Could not parse otpauth URI")?;
+        if uri.scheme() != "otpauth" {
+            bail!("URI has scheme {} instead of otpauth", uri.scheme());
+        }
+        if uri.host_str() != Some("totp") {
+            bail!(
+                "Only time-based one-time passwords (otpauth://totp/...) are supported but the URI is of type {}",
+                uri.host_str().unwrap_or_default()
+            );
+        }
+
+        let mut secret = None;
+        let mut algorithm = Algorithm::Sha1;
+        let mut digits = 6;
+        let mut period = 30;
+        for (key, value) in uri.query_pairs() {
+            match key.as_ref() {
+                "secret" => {
+                    let encoded = value.replace(' ', "").to_uppercase();
+                    secret = Some(
+                        base32::decode(
+                            base32::Alphabet::RFC4648 { padding: false },
+                            encoded.trim_end_matches('='),
+                        )
+                        .context("otpauth URI contains a secret which is not valid base32")?,
+                    );
+                }
+                "algorithm" => {
+                    algorithm = match value.to_uppercase().as_str() {
+                        "SHA1" => Algorithm::Sha1,
+                        "SHA256" => Algorithm::Sha256,
+                        "SHA512" => Algorithm::Sha512,
+                        _ => bail!("otpauth URI uses unsupported algorithm {value}"),
+                    }
+                }
+                "digits" => {
+                    digits = value
+                        .parse()
+                        .ok()
+                        .filter(|digits| (1..=9).contains(digits))
+                        .context(format!(
+                            "otpauth URI contains invalid digits {value} (must be between 1 and 9)"
+                        ))?
+                }
+                "period" => {
+                    period = value
+                        .parse()
+                        .ok()
+                        .filter(|period| *period > 0)
+                        .context(format!("otpauth URI contains invalid period {value}"))?
+                }
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            secret: secret.context("otpauth URI does not contain a secret")?,
+            algorithm,
+            digits,
+            period,
+        })
+    }
+
+    /// Generate the one-time password which is valid at *time*
+    pub(crate) fn generate(&self, time: SystemTime) -> anyhow::Result<String> {
+        let counter = time
+            .duration_since(UNIX_EPOCH)
+            .context("System time is before the unix epoch")?
+            .as_secs()
+            / self.period;
+
+        let hash = match self.algorithm {
+            Algorithm::Sha1 => hmac_digest::<Hmac<Sha1>>(&self.secret, counter),
+            Algorithm::Sha256 => hmac_digest::<Hmac<Sha256>>(&self.secret, counter),
+            Algorithm::Sha512 => hmac_digest::<Hmac<Sha512>>(&self.secret, counter),
+        }?;
+
+        // dynamic truncation as described in RFC 4226
+        let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+        let binary = u32::from_be_bytes([
+            hash[offset] & 0x7f,
+            hash[offset + 1],
+            hash[offset + 2],
+            hash[offset + 3],
+        ]);
+        let code = binary % 10u32.pow(self.digits);
+
+        Ok(format!("{:0width$}", code, width = self.digits as usize))
+    }
+}
+
+/// Compute the HMAC of the big-endian encoded *counter* using *key*
+fn hmac_digest<M: Mac + KeyInit>(key: &[u8], counter: u64) -> anyhow::Result<Vec<u8>> {
+    let mut mac = <M as KeyInit>::new_from_slice(key)
+        .map_err(|e| anyhow!("Could not initialize HMAC with otp secret: {e}"))?;
+    mac.update(&counter.to_be_bytes());
+    Ok(mac.finalize().into_bytes().to_vec())
+}
+
+/// Generate the current one-time password for the given `otpauth://` URI
+pub(crate) fn generate_current(uri: &str) -> anyhow::Result<String> {
+    TotpParameters::from_uri(uri)?.generate(SystemTime::now())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// Build an otpauth URI for the raw *secret* with additional query *parameters*
+    fn uri(secret: &[u8], parameters: &str) -> String {
+        let secret = base32::encode(base32::Alphabet::RFC4648 { padding: false }, secret);
+        format!("otpauth://totp/Example:alice@example.com?secret={secret}{parameters}")
+    }
+
+    fn at(seconds: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(seconds)
+    }
+
+    #[test]
+    fn rfc6238_test_vectors() {
+        let sha1_seed = b"12345678901234567890".as_slice();
+        let sha256_seed = b"12345678901234567890123456789012".as_slice();
+        let sha512_seed =
+            b"1234567890123456789012345678901234567890123456789012345678901234".as_slice();
+        let vectors = [
+            (sha1_seed, "SHA1", 59, "94287082"),
+            (sha256_seed, "SHA256", 59, "46119246"),
+            (sha512_seed, "SHA512", 59, "90693936"),
+            (sha1_seed, "SHA1", 1111111109, "07081804"),
+            (sha256_seed, "SHA256", 1111111109, "68084774"),
+            (sha512_seed, "SHA512", 1111111109, "25091201"),
+        ];
+
+        for (seed, algorithm, time, expected) in vectors {
+            let parameters =
+                TotpParameters::from_uri(&uri(seed, &format!("&algorithm={algorithm}&digits=8")))
+                    .unwrap();
+            assert_eq!(
+                parameters.generate(at(time)).unwrap(),
+                expected,
+                "{algorithm} at {time}"
+            );
+        }
+    }
+
+    #[test]
+    fn defaults_are_sha1_with_six_digits_and_thirty_seconds() {
+        let parameters = TotpParameters::from_uri(&uri(b"12345678901234567890", "")).unwrap();
+        assert_eq!(parameters.algorithm, Algorithm::Sha1);
+        assert_eq!(parameters.digits, 6);
+        assert_eq!(parameters.period, 30);
+        assert_eq!(parameters.generate(at(59)).unwrap(), "287082");
+    }
+
+    #[test]
+    fn lowercase_and_padded_secrets_are_accepted() {
+        let expected = b"12345678901".to_vec();
+        for secret in [
+            "GEZDGNBVGY3TQOJQGE",
+            "gezdgnbvgy3tqojqge",
+            "GEZDGNBVGY3TQOJQGE======",
+            "gezd gnbv gy3t qojq ge",
+        ] {
+            let parameters =
+                TotpParameters::from_uri(&format!("otpauth://totp/Example?secret={secret}"))
+                    .unwrap();
+            assert_eq!(parameters.secret, expected, "{secret}");
+        }
+    }
+
+    #[test]
+    fn digits_period_and_algorithm_are_parsed() {
+        let parameters = TotpParameters::from_uri(&uri(
+            b"12345678901234567890123456789012",
+            "&algorithm=sha256&digits=8&period=60",
+        ))
+        .unwrap();
+        assert_eq!(parameters.algorithm, Algorithm::Sha256);
+        assert_eq!(parameters.digits, 8);
+        assert_eq!(parameters.period, 60);
+        // with a period of 60 seconds, T=118 uses the same counter as T=59 with a period of 30 seconds
+        assert_eq!(parameters.generate(at(118)).unwrap(), "46119246");
+    }
+
+    #[test]
+    fn invalid_uris_are_rejected() {
+        for invalid in [
+            "otpauth://totp/Example?issuer=Example",
+            "otpauth://hotp/Example?secret=GEZDGNBV&counter=1",
+            "otpauth://totp/Example?secret=not-base32!",
+            "otpauth://totp/Example?secret=GEZDGNBV&digits=10",
+            "otpauth://totp/Example?secret=GEZDGNBV&period=0",
+            "otpauth://totp/Example?secret=GEZDGNBV&algorithm=MD5",
+        ] {
+            assert!(TotpParameters::from_uri(invalid).is_err(), "{invalid}");
+        }
+        assert_eq!(
+            TotpParameters::from_uri("otpauth://totp/Example?issuer=Example")
+                .unwrap_err()
+                .to_string(),
+            "otpauth URI does not contain a secret"
+        );
+    }
+}
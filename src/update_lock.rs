@@ -0,0 +1,127 @@
+//! Recording of the current commits of all git sources in the lockfile
+use crate::k8s_types::{K8sTypeId, PassSource, V1Beta1PassTransformer};
+use crate::lockfile::{Lockfile, LOCKFILE_NAME};
+use crate::results::ErrorCollection;
+use crate::V1Beta1PassSecret;
+use anyhow::{bail, Context};
+use serde::Deserialize;
+use serde_yaml::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// File names under which kustomize looks for a kustomization
+const KUSTOMIZATION_FILE_NAMES: [&str; 3] =
+    ["kustomization.yaml", "kustomization.yml", "Kustomization"];
+
+/// The parts of a kustomization that reference function configurations
+#[derive(Deserialize, Debug, Default)]
+struct Kustomization {
+    #[serde(default)]
+    generators: Vec<String>,
+    #[serde(default)]
+    transformers: Vec<String>,
+}
+
+/// Files of all generators and transformers of the kustomization in the current directory
+pub(crate) fn kustomization_config_files() -> anyhow::Result<Vec<PathBuf>> {
+    let kustomization_file = KUSTOMIZATION_FILE_NAMES
+        .iter()
+        .map(Path::new)
+        .find(|path| path.is_file())
+        .context(
+            "The current directory does not contain a kustomization and no files were given",
+        )?;
+    log::debug!("Reading kustomization {}", kustomization_file.display());
+
+    let kustomization: Kustomization =
+        serde_yaml::from_str(&fs::read_to_string(kustomization_file).context(format!(
+            "Could not read kustomization {}",
+            kustomization_file.display()
+        ))?)
+        .context(format!(
+            "Could not parse kustomization {}",
+            kustomization_file.display()
+        ))?;
+
+    // only files can contain function configurations while directories are separate kustomizations
+    Ok(kustomization
+        .generators
+        .into_iter()
+        .chain(kustomization.transformers)
+        .map(PathBuf::from)
+        .filter(|path| path.is_file())
+        .collect())
+}
+
+/// The sources of all PassSecrets and PassTransformers contained in *file*
+pub(crate) fn sources_from_file(file: &Path) -> anyhow::Result<Vec<PassSource>> {
+    let content = fs::read_to_string(file).context(format!("Could not read {}", file.display()))?;
+
+    let mut sources = Vec::new();
+    for document in serde_yaml::Deserializer::from_str(&content) {
+        let document =
+            Value::deserialize(document).context(format!("Could not parse {}", file.display()))?;
+        let kind = serde_yaml::from_value::<K8sTypeId>(document.clone())
+            .map(|type_id| type_id.kind)
+            .unwrap_or_default();
+        sources.push(match kind.as_str() {
+            "PassSecret" => {
+                serde_yaml::from_value::<V1Beta1PassSecret>(document)
+                    .context(format!("Could not parse PassSecret in {}", file.display()))?
+                    .source
+            }
+            "PassTransformer" => {
+                serde_yaml::from_value::<V1Beta1PassTransformer>(document)
+                    .context(format!(
+                        "Could not parse PassTransformer in {}",
+                        file.display()
+                    ))?
+                    .source
+            }
+            _ => continue,
+        });
+    }
+    Ok(sources)
+}
+
+/// Update the locked commits of all git sources of PassSecrets and PassTransformers contained in *file*
+fn update_lock_from_file(file: &Path, lockfile: &mut Lockfile) -> anyhow::Result<()> {
+    log::debug!("Updating lockfile with sources from {}", file.display());
+    for source in sources_from_file(file)? {
+        source.update_lock(lockfile)?;
+    }
+    Ok(())
+}
+
+/// Resolve all git sources which are configured in *files* again and record their commits in the lockfile
+///
+/// If no files are given, the generators and transformers of the kustomization in the current directory are used
+/// and entries of sources which are no longer used are removed from the lockfile.
+pub(crate) fn update_lock(files: &[PathBuf]) -> anyhow::Result<()> {
+    let lockfile_path = Path::new(LOCKFILE_NAME);
+    let (files, mut lockfile) = if files.is_empty() {
+        (kustomization_config_files()?, Lockfile::default())
+    } else {
+        (
+            files.to_vec(),
+            Lockfile::load(lockfile_path)?.unwrap_or_default(),
+        )
+    };
+    if files.is_empty() {
+        bail!("There are no generators or transformers whose sources could be locked");
+    }
+
+    let errors = files
+        .iter()
+        .filter_map(|file| update_lock_from_file(file, &mut lockfile).err())
+        .collect();
+    ErrorCollection(errors).into_result()?;
+
+    lockfile.store(lockfile_path)?;
+    log::info!(
+        "Recorded {} sources in {}",
+        lockfile.sources.len(),
+        lockfile_path.display()
+    );
+    Ok(())
+}
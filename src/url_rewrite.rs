@@ -0,0 +1,221 @@
+//! Rewriting of git source urls through git configuration and plugin level overrides
+//!
+//! This allows manifests to reference the canonical url of a repository while it is actually accessed through
+//! e.g. an internal mirror.
+use crate::env_config;
+use anyhow::Context;
+use directories::ProjectDirs;
+use git2::Config;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+/// Name of the file in the configuration directory of this plugin which contains url overrides
+const URL_OVERRIDES_FILE_NAME: &str = "url-overrides.yml";
+
+/// A rule which replaces a url prefix by another one
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Rewrite {
+    prefix: String,
+    replacement: String,
+}
+
+/// Apply the rewrite with the longest matching prefix of *rewrites* to *url*
+///
+/// This is the same rule by which git chooses between multiple `insteadOf` rules.
+fn apply_longest_match(url: &str, rewrites: &[Rewrite]) -> Option<String> {
+    rewrites
+        .iter()
+        .filter(|rewrite| url.starts_with(&rewrite.prefix))
+        .max_by_key(|rewrite| rewrite.prefix.len())
+        .map(|rewrite| rewrite.replacement.clone() + &url[rewrite.prefix.len()..])
+}
+
+/// Path of the configuration file which contains url overrides
+fn url_overrides_file() -> Option<PathBuf> {
+    ProjectDirs::from("de", "ftsell", "kustomize-pass")
+        .map(|dirs| dirs.config_dir().join(URL_OVERRIDES_FILE_NAME))
+}
+
+/// Url overrides of this plugin from its configuration file and the environment
+///
+/// Overrides from the environment take precedence over those from the configuration file.
+fn url_overrides() -> anyhow::Result<Vec<Rewrite>> {
+    let mut overrides = BTreeMap::new();
+    if let Some(path) = url_overrides_file().filter(|path| path.is_file()) {
+        log::trace!("Reading url overrides from {}", path.display());
+        let content = fs::read_to_string(&path).context(format!(
+            "Could not read url overrides from {}",
+            path.display()
+        ))?;
+        let file_overrides: BTreeMap<String, String> = serde_yaml::from_str(&content).context(
+            format!("Could not parse url overrides in {}", path.display()),
+        )?;
+        overrides.extend(file_overrides);
+    }
+    overrides.extend(env_config::url_overrides()?);
+
+    Ok(overrides
+        .into_iter()
+        .map(|(prefix, replacement)| Rewrite {
+            prefix,
+            replacement,
+        })
+        .collect())
+}
+
+/// `url.<base>.insteadOf` rules of the git configuration
+fn git_rewrites() -> anyhow::Result<Vec<Rewrite>> {
+    let config = Config::open_default().context("Could not open git configuration")?;
+    rewrites_of(&config)
+}
+
+/// `url.<base>.insteadOf` rules of *config*
+fn rewrites_of(config: &Config) -> anyhow::Result<Vec<Rewrite>> {
+    let mut rewrites = Vec::new();
+    config
+        .entries(Some(r"url\..*\.insteadof"))?
+        .for_each(|entry| {
+            // the base is the subsection of the configuration key and may contain dots itself
+            let base = entry
+                .name()
+                .and_then(|name| name.strip_prefix("url."))
+                .and_then(|name| name.strip_suffix(".insteadof"));
+            if let (Some(base), Some(prefix)) = (base, entry.value()) {
+                rewrites.push(Rewrite {
+                    prefix: prefix.to_string(),
+                    replacement: base.to_string(),
+                });
+            }
+        })?;
+    Ok(rewrites)
+}
+
+/// Rewrite *url* according to the url overrides of this plugin only
+///
+/// This is the url which is given to git and libgit2 because both apply the `insteadOf` and `pushInsteadOf` rules
+/// of the git configuration themselves.
+pub(crate) fn apply_overrides(url: &str) -> anyhow::Result<String> {
+    let result = apply_longest_match(url, &url_overrides()?).unwrap_or_else(|| url.to_string());
+    if result != url {
+        log::debug!("Overrode url {} with {}", url, result);
+    }
+    Ok(result)
+}
+
+/// Rewrite *url* according to the url overrides of this plugin and the `insteadOf` rules of git
+///
+/// Plugin overrides are applied first and `insteadOf` rules are applied to the result, which yields the url from
+/// which the repository is actually fetched.
+pub(crate) fn rewrite_url(url: &str) -> anyhow::Result<String> {
+    let result = rewrite(url, &url_overrides()?, &git_rewrites()?);
+    if result != url {
+        log::debug!("Rewrote url {} to {}", url, result);
+    }
+    Ok(result)
+}
+
+/// Rewrite *url* with the longest matching rule of *overrides* and then with that of *git_rewrites*
+///
+/// Each list is applied at most once, just like git applies only one `insteadOf` rule to a url.
+fn rewrite(url: &str, overrides: &[Rewrite], git_rewrites: &[Rewrite]) -> String {
+    let overridden = apply_longest_match(url, overrides).unwrap_or_else(|| url.to_string());
+    apply_longest_match(&overridden, git_rewrites).unwrap_or(overridden)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    fn rewrites(rules: &[(&str, &str)]) -> Vec<Rewrite> {
+        rules
+            .iter()
+            .map(|(prefix, replacement)| Rewrite {
+                prefix: prefix.to_string(),
+                replacement: replacement.to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn longest_prefix_is_applied() {
+        let rules = rewrites(&[
+            ("https://github.com/", "https://mirror.example.com/github/"),
+            ("https://github.com/org/", "https://mirror.example.com/org/"),
+            ("https://github", "https://other.example.com/"),
+        ]);
+        assert_eq!(
+            apply_longest_match("https://github.com/org/repo.git", &rules).as_deref(),
+            Some("https://mirror.example.com/org/repo.git")
+        );
+        assert_eq!(
+            apply_longest_match("https://github.com/user/repo.git", &rules).as_deref(),
+            Some("https://mirror.example.com/github/user/repo.git")
+        );
+    }
+
+    #[test]
+    fn urls_without_matching_rule_are_kept() {
+        let rules = rewrites(&[("https://github.com/", "https://mirror.example.com/")]);
+        assert_eq!(
+            apply_longest_match("https://gitlab.com/repo.git", &rules),
+            None
+        );
+        assert_eq!(
+            rewrite("https://gitlab.com/repo.git", &rules, &rules),
+            "https://gitlab.com/repo.git"
+        );
+        assert_eq!(
+            rewrite("https://gitlab.com/repo.git", &[], &[]),
+            "https://gitlab.com/repo.git"
+        );
+    }
+
+    #[test]
+    fn overrides_are_applied_before_and_insteadof_only_once() {
+        let overrides = rewrites(&[("https://github.com/", "gh:")]);
+        let git_rewrites = rewrites(&[
+            ("gh:", "https://github.com/"),
+            ("https://github.com/", "https://mirror.example.com/"),
+        ]);
+        // the result of an insteadOf rule is not rewritten again, just like in git
+        assert_eq!(
+            rewrite("gh:org/repo.git", &[], &git_rewrites),
+            "https://github.com/org/repo.git"
+        );
+        assert_eq!(
+            rewrite("https://github.com/org/repo.git", &overrides, &git_rewrites),
+            "https://github.com/org/repo.git"
+        );
+        assert_eq!(
+            rewrite("https://github.com/org/repo.git", &[], &git_rewrites),
+            "https://mirror.example.com/org/repo.git"
+        );
+    }
+
+    #[test]
+    fn insteadof_rules_are_read_from_git_configuration() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(
+            b"[url \"https://mirror.example.com/\"]\n\
+              \tinsteadOf = https://github.com/\n\
+              \tinsteadOf = gh:\n\
+              [url \"ssh://git@git.example.com/\"]\n\
+              \tinsteadOf = https://git.example.com/\n",
+        )
+        .unwrap();
+        let config = Config::open(file.path()).unwrap();
+        let mut rules = rewrites_of(&config).unwrap();
+        rules.sort_by(|a, b| a.prefix.cmp(&b.prefix));
+        assert_eq!(
+            rules,
+            rewrites(&[
+                ("gh:", "https://mirror.example.com/"),
+                ("https://git.example.com/", "ssh://git@git.example.com/"),
+                ("https://github.com/", "https://mirror.example.com/"),
+            ])
+        );
+    }
+}
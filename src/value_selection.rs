@@ -0,0 +1,351 @@
+//! Parsing of references to pass entries and selection of parts of their content
+use crate::totp;
+use anyhow::{anyhow, bail, Context};
+use serde_yaml::Value;
+
+/// File extensions of pass names whose entries are interpreted as structured JSON or YAML documents
+const STRUCTURED_EXTENSIONS: [&str; 3] = [".json", ".yaml", ".yml"];
+
+/// A reference to an entry in pass and the part of its content that should be used
+///
+/// References are written as `<pass-name>` or `<pass-name>#<selector>`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct PassReference<'a> {
+    /// Name of the entry in pass
+    pub(crate) pass_name: &'a str,
+
+    /// Which part of the entries content should be used
+    pub(crate) selector: Selector<'a>,
+}
+
+/// Selection of a part of a pass entries content
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) enum Selector<'a> {
+    /// The complete content (only stripped of a trailing newline)
+    ///
+    /// If the entry consists of nothing but an `otpauth://` URI, the current one-time password is generated
+    /// from it instead.
+    Whole,
+
+    /// The current one-time password generated from the first `otpauth://` URI contained in the entry (`#otp`)
+    Otp,
+
+    /// The first line which by convention contains the password (`#password`)
+    FirstLine,
+
+    /// A specific line given by its 1-based line number (`#line:<n>`)
+    Line(usize),
+
+    /// The value of a `key: value` field on any line but the first (`#<key>`)
+    Field(&'a str),
+
+    /// A value inside a JSON or YAML document
+    ///
+    /// This is used for selectors starting with `$` (e.g. `#$.private_key`) and for all other selectors of
+    /// entries whose names end with `.json`, `.yaml` or `.yml` (e.g. `#db.password`).
+    /// An empty path (`#$`) selects the whole document.
+    Path(Vec<PathSegment<'a>>),
+}
+
+/// A single segment of a [`Selector::Path`]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) enum PathSegment<'a> {
+    /// Key of a mapping (`.key`)
+    Key(&'a str),
+
+    /// Index into a sequence (`[index]`)
+    Index(usize),
+}
+
+impl<'a> PassReference<'a> {
+    /// Parse a reference of the form `<pass-name>` or `<pass-name>#<selector>`
+    pub(crate) fn parse(reference: &'a str) -> anyhow::Result<Self> {
+        let (pass_name, selector) = match reference.split_once('#') {
+            None => (reference, Selector::Whole),
+            Some((pass_name, selector)) => (pass_name, Selector::parse(pass_name, selector)?),
+        };
+
+        Ok(Self {
+            pass_name,
+            selector,
+        })
+    }
+}
+
+impl<'a> Selector<'a> {
+    fn parse(pass_name: &str, selector: &'a str) -> anyhow::Result<Self> {
+        let is_structured = STRUCTURED_EXTENSIONS
+            .iter()
+            .any(|extension| pass_name.ends_with(extension));
+
+        if let Some(path) = selector.strip_prefix('$') {
+            // a bare `$` selects the whole document
+            match path.strip_prefix('.').unwrap_or(path) {
+                "" => Ok(Self::Path(Vec::new())),
+                path => Ok(Self::Path(
+                    parse_path(path).context(format!("Selector {selector} is invalid"))?,
+                )),
+            }
+        } else if selector == "password" {
+            Ok(Self::FirstLine)
+        } else if selector == "otp" {
+            Ok(Self::Otp)
+        } else if let Some(line_number) = selector.strip_prefix("line:") {
+            match line_number.parse() {
+                Ok(0) | Err(_) => Err(anyhow!(
+                    "Line selector {selector} is invalid because line numbers must be positive integers"
+                )),
+                Ok(line_number) => Ok(Self::Line(line_number)),
+            }
+        } else if selector.is_empty() {
+            Err(anyhow!("Selector must not be empty"))
+        } else if is_structured {
+            Ok(Self::Path(
+                parse_path(selector).context(format!("Selector {selector} is invalid"))?,
+            ))
+        } else {
+            Ok(Self::Field(selector))
+        }
+    }
+
+    /// Select the part of *content* which is described by this selector
+    pub(crate) fn select(&self, content: &str) -> anyhow::Result<String> {
+        match self {
+            Self::Whole => {
+                let content = content.strip_suffix('\n').unwrap_or(content);
+                if content.starts_with(totp::OTPAUTH_SCHEME) && !content.contains('\n') {
+                    totp::generate_current(content.trim())
+                } else {
+                    Ok(content.to_string())
+                }
+            }
+            Self::Otp => content
+                .lines()
+                .map(str::trim)
+                .find(|line| line.starts_with(totp::OTPAUTH_SCHEME))
+                .context("Entry does not contain an otpauth:// URI")
+                .and_then(totp::generate_current),
+            Self::FirstLine => Ok(content.lines().next().unwrap_or_default().to_string()),
+            Self::Line(line_number) => content
+                .lines()
+                .nth(line_number - 1)
+                .map(str::to_string)
+                .context(format!("Entry does not have a line {line_number}")),
+            Self::Field(key) => content
+                .lines()
+                .skip(1)
+                .filter_map(|line| line.split_once(':'))
+                .find(|(i_key, _)| i_key.trim().eq_ignore_ascii_case(key))
+                .map(|(_, value)| value.trim().to_string())
+                .context(format!("Entry does not contain a field named {key}")),
+            Self::Path(path) => {
+                let document: Value = serde_yaml::from_str(content)
+                    .context("Entry cannot be parsed as JSON or YAML document")?;
+                let value = path
+                    .iter()
+                    .try_fold(&document, |value, segment| match segment {
+                        PathSegment::Key(key) => value.get(key),
+                        PathSegment::Index(index) => value.get(index),
+                    })
+                    .context(format!(
+                        "Entry does not contain a value at path {}",
+                        format_path(path)
+                    ))?;
+
+                match value {
+                    Value::String(value) => Ok(value.to_owned()),
+                    Value::Number(value) => Ok(value.to_string()),
+                    Value::Bool(value) => Ok(value.to_string()),
+                    Value::Null => Ok(String::new()),
+                    Value::Sequence(_) | Value::Mapping(_) => serde_json::to_string(value)
+                        .context("Could not encode selected value as JSON"),
+                }
+            }
+        }
+    }
+}
+
+/// Parse a path of the form `key.other_key[0].final_key` into its segments
+fn parse_path(path: &str) -> anyhow::Result<Vec<PathSegment<'_>>> {
+    let mut segments = Vec::new();
+    for part in path.split('.') {
+        // each part consists of an optional key followed by any number of [index] suffixes
+        let (key, mut indices) = part.split_at(part.find('[').unwrap_or(part.len()));
+        if !key.is_empty() {
+            segments.push(PathSegment::Key(key));
+        } else if indices.is_empty() {
+            bail!("Path {path} contains an empty segment");
+        }
+
+        while !indices.is_empty() {
+            let (index, rest) = indices
+                .strip_prefix('[')
+                .and_then(|indices| indices.split_once(']'))
+                .context(format!("Path {path} contains an unterminated index"))?;
+            segments.push(PathSegment::Index(index.parse().context(format!(
+                "Path {path} contains the index {index} which is not a non-negative integer"
+            ))?));
+            indices = rest;
+        }
+    }
+
+    Ok(segments)
+}
+
+/// Format a parsed path again so that it can be shown to users
+fn format_path(path: &[PathSegment]) -> String {
+    let mut result = String::from("$");
+    for segment in path.iter() {
+        match segment {
+            PathSegment::Key(key) => {
+                result.push('.');
+                result.push_str(key);
+            }
+            PathSegment::Index(index) => result.push_str(&format!("[{index}]")),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Content of a typical pass entry with a password on the first line and fields below it
+    const ENTRY: &str = "hunter2\nusername: alice\nURL: https://example.com:8443/login\n";
+
+    fn select(reference: &str, content: &str) -> anyhow::Result<String> {
+        PassReference::parse(reference)?.selector.select(content)
+    }
+
+    #[test]
+    fn references_without_selector_use_the_whole_entry() {
+        assert_eq!(
+            PassReference::parse("app/db").unwrap(),
+            PassReference {
+                pass_name: "app/db",
+                selector: Selector::Whole,
+            }
+        );
+        assert_eq!(select("app/db", ENTRY).unwrap(), ENTRY.trim_end());
+        assert_eq!(select("app/db", "line\n\n").unwrap(), "line\n");
+    }
+
+    #[test]
+    fn lines_are_selected_by_number() {
+        assert_eq!(select("app/db#password", ENTRY).unwrap(), "hunter2");
+        assert_eq!(select("app/db#line:1", ENTRY).unwrap(), "hunter2");
+        assert_eq!(select("app/db#line:2", ENTRY).unwrap(), "username: alice");
+        assert!(select("app/db#line:4", ENTRY).is_err());
+        assert!(PassReference::parse("app/db#line:0").is_err());
+        assert!(PassReference::parse("app/db#line:-1").is_err());
+        assert!(PassReference::parse("app/db#line:two").is_err());
+    }
+
+    #[test]
+    fn fields_are_selected_by_key() {
+        assert_eq!(select("app/db#username", ENTRY).unwrap(), "alice");
+        assert_eq!(
+            select("app/db#url", ENTRY).unwrap(),
+            "https://example.com:8443/login"
+        );
+        // the first line is the password and never a field
+        assert_eq!(
+            select("app/db#user", "user: root\nuser: alice\n").unwrap(),
+            "alice"
+        );
+        assert!(select("app/db#user", "user: root\n").is_err());
+        assert!(select("app/db#missing", ENTRY).is_err());
+        assert!(PassReference::parse("app/db#").is_err());
+    }
+
+    /// Structured entry which is used to test path selection
+    const DOCUMENT: &str =
+        r#"{"a": {"b": "value", "n": 5, "t": true, "z": null}, "list": [[1, 2], [3, 4, "five"]]}"#;
+
+    #[test]
+    fn paths_are_parsed_into_segments() {
+        assert_eq!(
+            parse_path("a.b").unwrap(),
+            vec![PathSegment::Key("a"), PathSegment::Key("b")]
+        );
+        assert_eq!(
+            parse_path("a[1][2]").unwrap(),
+            vec![
+                PathSegment::Key("a"),
+                PathSegment::Index(1),
+                PathSegment::Index(2)
+            ]
+        );
+        assert_eq!(parse_path("[0]").unwrap(), vec![PathSegment::Index(0)]);
+        assert_eq!(
+            format_path(&parse_path("a[1][2].b").unwrap()),
+            "$.a[1][2].b"
+        );
+        for invalid in ["a..b", "a.", "a[1", "a[x]", "a[-1]"] {
+            assert!(parse_path(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn dollar_selectors_are_paths() {
+        assert_eq!(
+            PassReference::parse("app/db#$").unwrap().selector,
+            Selector::Path(Vec::new())
+        );
+        assert_eq!(
+            PassReference::parse("app/db#$.").unwrap().selector,
+            Selector::Path(Vec::new())
+        );
+        assert_eq!(
+            PassReference::parse("app/db#$.a.b").unwrap().selector,
+            Selector::Path(vec![PathSegment::Key("a"), PathSegment::Key("b")])
+        );
+        assert_eq!(
+            PassReference::parse("app/db#$[0]").unwrap().selector,
+            Selector::Path(vec![PathSegment::Index(0)])
+        );
+        // selectors of structured entries are paths even without `$`
+        assert_eq!(
+            PassReference::parse("app/db.yaml#a.b").unwrap().selector,
+            Selector::Path(vec![PathSegment::Key("a"), PathSegment::Key("b")])
+        );
+    }
+
+    #[test]
+    fn values_are_selected_by_path() {
+        assert_eq!(select("sa.json#a.b", DOCUMENT).unwrap(), "value");
+        assert_eq!(select("sa#$.a.n", DOCUMENT).unwrap(), "5");
+        assert_eq!(select("sa#$.a.t", DOCUMENT).unwrap(), "true");
+        assert_eq!(select("sa#$.a.z", DOCUMENT).unwrap(), "");
+        assert_eq!(select("sa#$.list[1][2]", DOCUMENT).unwrap(), "five");
+        assert_eq!(select("sa#$[0]", "- first\n- second\n").unwrap(), "first");
+    }
+
+    #[test]
+    fn non_scalar_values_are_selected_as_json() {
+        assert_eq!(select("sa#$.list[0]", DOCUMENT).unwrap(), "[1,2]");
+        assert_eq!(
+            select("sa#$.a", DOCUMENT).unwrap(),
+            r#"{"b":"value","n":5,"t":true,"z":null}"#
+        );
+        assert_eq!(
+            select("sa#$", "key: value\n").unwrap(),
+            r#"{"key":"value"}"#
+        );
+    }
+
+    #[test]
+    fn missing_values_are_errors() {
+        for reference in ["sa#$.missing", "sa#$.a.b.c", "sa#$.list[2]", "sa#$.a[0]"] {
+            let error = select(reference, DOCUMENT).unwrap_err();
+            assert!(
+                error
+                    .to_string()
+                    .starts_with("Entry does not contain a value at path $"),
+                "{reference}: {error}"
+            );
+        }
+        assert!(select("sa#$.a", "{ invalid").is_err());
+    }
+}